@@ -0,0 +1,45 @@
+// A minimal timing harness shared by the benchmarks, so they run on stable
+// without pulling in a benchmarking framework.
+
+// Each benchmark only uses part of the harness.
+#![allow(dead_code)]
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Times f and prints the fastest and the median duration of a run. Only does
+// work when run through `cargo bench`, so `cargo test --all-targets` stays
+// fast.
+pub fn bench<R>(name: &str, mut f: impl FnMut() -> R) {
+    bench_with_setup(name, || (), |()| f());
+}
+
+// Like bench, but every run gets a fresh input from setup, which is not timed.
+pub fn bench_with_setup<S, R>(name: &str, mut setup: impl FnMut() -> S, mut f: impl FnMut(S) -> R) {
+    // Like libtest, any argument that is not a flag filters by name.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut filters = args.iter().filter(|arg| !arg.starts_with('-')).peekable();
+    if !args.iter().any(|arg| arg == "--bench")
+        || (filters.peek().is_some() && !filters.any(|filter| name.contains(filter.as_str())))
+    {
+        return;
+    }
+
+    let mut samples = Vec::new();
+    let start = Instant::now();
+    while samples.len() < 10 || (samples.len() < 1000 && start.elapsed() < Duration::from_secs(2)) {
+        let input = black_box(setup());
+        let run = Instant::now();
+        let output = black_box(f(input));
+        samples.push(run.elapsed());
+        drop(output);
+    }
+
+    samples.sort();
+    println!(
+        "{:<56} min {:>10.1?}  median {:>10.1?}",
+        name,
+        samples[0],
+        samples[samples.len() / 2]
+    );
+}
@@ -0,0 +1,33 @@
+// Compares bulk insertion with insert_from_iter against inserting one
+// element at a time. Run with `cargo bench --bench insert`.
+
+mod common;
+
+use common::bench;
+use slotmap::{HopSlotMap, SlotMap};
+
+const N: u32 = 50_000;
+
+fn main() {
+    bench("SlotMap insert loop, 50k", || {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..N).map(|i| sm.insert(i)).collect();
+        (sm, keys)
+    });
+    bench("SlotMap insert_from_iter, 50k", || {
+        let mut sm = SlotMap::new();
+        let keys = sm.insert_from_iter(0..N);
+        (sm, keys)
+    });
+
+    bench("HopSlotMap insert loop, 50k", || {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..N).map(|i| sm.insert(i)).collect();
+        (sm, keys)
+    });
+    bench("HopSlotMap insert_from_iter, 50k", || {
+        let mut sm = HopSlotMap::new();
+        let keys = sm.insert_from_iter(0..N);
+        (sm, keys)
+    });
+}
@@ -0,0 +1,81 @@
+// Iteration over slot maps with many vacant slots. Run with
+// `cargo bench --bench iter`.
+
+mod common;
+
+use common::bench;
+use slotmap::{DefaultKey, DenseSlotMap, HopSlotMap, SlotMap};
+
+// A slot map that grew to 200k elements of which only the first 100 are left.
+fn mostly_empty_slot_map() -> SlotMap<DefaultKey, u64> {
+    let mut sm = SlotMap::new();
+    let keys: Vec<_> = (0..200_000).map(|i| sm.insert(i)).collect();
+    for &k in &keys[100..] {
+        sm.remove(k);
+    }
+    sm
+}
+
+// A hop slot map with 100 elements spread out over 200k slots, so between any
+// two elements lies a large block of vacant slots.
+fn fragmented_hop_slot_map() -> HopSlotMap<DefaultKey, u64> {
+    let mut sm = HopSlotMap::new();
+    let keys: Vec<_> = (0..200_000).map(|i| sm.insert(i)).collect();
+    for (i, &k) in keys.iter().enumerate() {
+        if i % 2000 != 0 {
+            sm.remove(k);
+        }
+    }
+    sm
+}
+
+// Whether element i of the occupancy benchmarks is kept, for roughly the
+// given percentage of the elements. A multiplicative hash spreads the removed
+// elements evenly over the slots.
+fn keep(i: u32, percent: u32) -> bool {
+    (i.wrapping_mul(0x9e37_79b9) >> 16) % 100 < percent
+}
+
+fn main() {
+    let compact: SlotMap<DefaultKey, u64> = (0..100).fold(SlotMap::new(), |mut sm, i| {
+        sm.insert(i);
+        sm
+    });
+    bench("SlotMap values, 100 elements in 100 slots", || compact.values().sum::<u64>());
+
+    // Removing elements doesn't look for the new highest occupied slot, so
+    // iterating from the back visits the vacated slots until retain finds it.
+    let mut sm = mostly_empty_slot_map();
+    bench("SlotMap values, 100 elements in 200k slots", || sm.values().sum::<u64>());
+    bench("SlotMap values rev, 100 elements in 200k slots", || sm.values().rev().sum::<u64>());
+    bench("SlotMap retain, 100 elements in 200k slots", || sm.retain(|_, _| true));
+    bench("SlotMap values rev after retain, 100 elements", || sm.values().rev().sum::<u64>());
+
+    let hsm = fragmented_hop_slot_map();
+    let spread: SlotMap<_, _> = hsm.clone().into();
+    bench("SlotMap values, 100 elements spread over 200k slots", || spread.values().sum::<u64>());
+    bench("HopSlotMap values, 100 elements spread over 200k slots", || hsm.values().sum::<u64>());
+
+    // Full iteration over each kind of slot map, all with the same elements
+    // left out of 100k slots.
+    for &percent in &[100, 50, 10, 1] {
+        let mut sm = SlotMap::new();
+        let mut hsm = HopSlotMap::new();
+        let mut dsm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..100_000u32)
+            .map(|i| (sm.insert(i as u64), hsm.insert(i as u64), dsm.insert(i as u64)))
+            .collect();
+        for (i, &(k, hk, dk)) in keys.iter().enumerate() {
+            if !keep(i as u32, percent) {
+                sm.remove(k);
+                hsm.remove(hk);
+                dsm.remove(dk);
+            }
+        }
+
+        let occupancy = format!("{}% of 100k slots occupied", percent);
+        bench(&format!("SlotMap values, {}", occupancy), || sm.values().sum::<u64>());
+        bench(&format!("HopSlotMap values, {}", occupancy), || hsm.values().sum::<u64>());
+        bench(&format!("DenseSlotMap values, {}", occupancy), || dsm.values().sum::<u64>());
+    }
+}
@@ -0,0 +1,45 @@
+// Removal patterns that should not depend on the number of slots, and batch
+// removal with remove_many against removing one key at a time. Run with
+// `cargo bench --bench remove`.
+
+mod common;
+
+use common::{bench, bench_with_setup};
+use slotmap::SlotMap;
+
+fn main() {
+    // Only the first and the last slot are occupied, and the last element is
+    // removed and inserted again, which reuses its slot every time.
+    for &(n, slots) in &[(10_000, "10k"), (100_000, "100k"), (1_000_000, "1M")] {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..n).map(|i| sm.insert(i)).collect();
+        for &k in &keys[1..n as usize - 1] {
+            sm.remove(k);
+        }
+
+        let mut top = keys[n as usize - 1];
+        bench(&format!("SlotMap remove and insert at the top x1000, {} slots", slots), || {
+            for _ in 0..1000 {
+                let value = sm.remove(top).unwrap();
+                top = sm.insert(value);
+            }
+        });
+    }
+
+    // Remove half of 100k elements, in a scattered order.
+    let mut sm = SlotMap::new();
+    let mut keys: Vec<_> = (0..100_000u32).map(|i| sm.insert(i)).collect();
+    keys.sort_by_key(|k| sm[*k].wrapping_mul(2_654_435_761));
+    keys.truncate(50_000);
+
+    bench_with_setup("SlotMap remove loop, 50k of 100k", || sm.clone(), |mut sm| {
+        for &k in &keys {
+            sm.remove(k);
+        }
+        sm
+    });
+    bench_with_setup("SlotMap remove_many, 50k of 100k", || sm.clone(), |mut sm| {
+        sm.remove_many(&keys);
+        sm
+    });
+}
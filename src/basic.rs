@@ -7,14 +7,14 @@
 use alloc::collections::TryReserveError;
 use alloc::vec::Vec;
 use core::fmt;
-use core::iter::{Enumerate, FusedIterator};
+use core::iter::{Enumerate, FromIterator, FusedIterator};
 use core::marker::PhantomData;
 #[allow(unused_imports)] // MaybeUninit is only used on nightly at the moment.
 use core::mem::{ManuallyDrop, MaybeUninit};
 use core::ops::{Index, IndexMut};
 
 use crate::util::{Never, UnwrapUnchecked};
-use crate::{DefaultKey, Key, KeyData};
+use crate::{DefaultKey, InvariantError, Key, KeyData, KeyError, ReusePolicy};
 
 // Storage inside a slot or metadata for the freelist when vacant.
 union SlotUnion<T> {
@@ -50,6 +50,25 @@ impl<T> Slot<T> {
         self.version % 2 > 0
     }
 
+    // Is this slot, stored at index idx, held by a reservation? Reserved slots
+    // are vacant and link to themselves instead of being on the freelist. Not
+    // meaningful for the sentinel.
+    #[inline(always)]
+    pub fn reserved(&self, idx: usize) -> bool {
+        !self.occupied() && unsafe { self.u.next_free } as usize == idx
+    }
+
+    // The version of this slot, stored at index idx, once the slot map is
+    // taken apart or serialized. A reserved slot becomes a vacant slot a
+    // version further, so the key of the reservation is never handed out.
+    pub fn exported_version(&self, idx: usize) -> u32 {
+        if idx > 0 && self.reserved(idx) {
+            self.version.wrapping_add(2).max(2)
+        } else {
+            self.version
+        }
+    }
+
     pub fn get(&self) -> SlotContent<T> {
         unsafe {
             if self.occupied() {
@@ -123,14 +142,82 @@ impl<T: fmt::Debug> fmt::Debug for Slot<T> {
     }
 }
 
+/// Occupancy statistics of a [`SlotMap`], returned by [`SlotMap::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct OccupancyStats {
+    /// The number of slots that are allocated, occupied or vacant. Does not
+    /// include the slots that are only reserved as spare capacity.
+    pub slots: usize,
+
+    /// The number of occupied slots, equal to the length of the slot map.
+    pub occupied: usize,
+
+    /// The number of vacant slots, either on the freelist or held by a
+    /// [`ReservedKey`].
+    pub vacant: usize,
+
+    /// The number of vacant slots on the freelist, waiting to be reused.
+    /// Unlike `vacant` this leaves out the slots held by a [`ReservedKey`].
+    pub free: usize,
+
+    /// The highest slot index that was ever handed out in a key, or `None`
+    /// if there is none. Every slot past the sentinel was added for a key,
+    /// inserted or reserved, so this is always the number of slots. Slots
+    /// released by [`SlotMap::shrink_to_fit`] no longer count.
+    pub max_index: Option<u32>,
+}
+
+/// A slot reserved by [`SlotMap::reserve_key`] that has not been filled yet.
+///
+/// The reservation must be handed back to the slot map it came from, either
+/// to [`SlotMap::insert_reserved`] or to [`SlotMap::cancel_reserved`].
+/// Dropping it instead leaves the slot reserved for as long as the slot map
+/// lives.
+#[derive(Debug, PartialEq, Eq, Hash)]
+#[must_use = "the reserved slot can not be reused until it is filled or cancelled"]
+pub struct ReservedKey<K: Key> {
+    key: K,
+}
+
+impl<K: Key> ReservedKey<K> {
+    /// Returns the key the value will have once it is inserted with
+    /// [`SlotMap::insert_reserved`]. Until then the key is not valid in the
+    /// slot map.
+    pub fn key(&self) -> K {
+        self.key
+    }
+}
+
 /// Slot map, storage with stable unique keys.
 ///
 /// See [crate documentation](crate) for more details.
-#[derive(Debug)]
+///
+/// # Iteration order
+///
+/// All iterators visit elements in ascending order of their slot index, and
+/// in descending order when iterated from the back. Which slot an element
+/// is stored in only depends on the operations performed on the slot map, so
+/// two slot maps with identical histories of operations iterate in identical
+/// order. A clone continues with the same history. Deserialization preserves
+/// the slot of every element, but rebuilds the freelist, which may place new
+/// elements differently than in the original slot map.
+///
+/// Which vacant slot is reused by the next insertion is determined by the
+/// [`ReusePolicy`] of the slot map, see
+/// [`set_reuse_policy`](Self::set_reuse_policy).
 pub struct SlotMap<K: Key, V> {
     slots: Vec<Slot<V>>,
     free_head: u32,
+    // Last slot on the freelist, only meaningful if the freelist is not empty.
+    free_tail: u32,
     num_elems: u32,
+    // Upper bound on the highest occupied slot, 0 if there never was one.
+    // Iteration never looks past it. Raised on insertion, but not lowered on
+    // removal, which would make removing at the top of a sparse slot map scan
+    // the whole gap below. Methods that iterate mutably tighten it instead.
+    max_occupied: u32,
+    policy: ReusePolicy,
     _k: PhantomData<fn(K) -> K>,
 }
 
@@ -199,9 +286,10 @@ impl<K: Key, V> SlotMap<K, V> {
     /// ```
     pub fn with_capacity_and_key(capacity: usize) -> Self {
         // Create slots with a sentinel at index 0.
-        // We don't actually use the sentinel for anything currently, but
-        // HopSlotMap does, and if we want keys to remain valid through
-        // conversion we have to have one as well.
+        // The sentinel is never occupied. Its version records the highest
+        // version of any slot released by shrink_to_fit, see there. HopSlotMap
+        // uses its sentinel as well, and if we want keys to remain valid
+        // through conversion we have to have one.
         let mut slots = Vec::with_capacity(capacity + 1);
         slots.push(Slot {
             u: SlotUnion { next_free: 0 },
@@ -211,11 +299,57 @@ impl<K: Key, V> SlotMap<K, V> {
         Self {
             slots,
             free_head: 1,
+            free_tail: 0,
             num_elems: 0,
+            max_occupied: 0,
+            policy: ReusePolicy::Lifo,
             _k: PhantomData,
         }
     }
 
+    /// Returns the [`ReusePolicy`] of the slot map. New slot maps use
+    /// [`ReusePolicy::Lifo`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let sm: SlotMap<_, i32> = SlotMap::new();
+    /// assert_eq!(sm.reuse_policy(), ReusePolicy::Lifo);
+    /// ```
+    pub fn reuse_policy(&self) -> ReusePolicy {
+        self.policy
+    }
+
+    /// Sets the order in which vacant slots are reused by future insertions.
+    /// The policy only affects slots vacated after the call, slots that are
+    /// already vacant keep their place in line.
+    ///
+    /// With [`ReusePolicy::Fifo`] the slot that has been vacant the longest is
+    /// reused first, which spreads version bumps over all vacant slots. The
+    /// policy is not serialized, a deserialized slot map uses
+    /// [`ReusePolicy::Lifo`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// sm.set_reuse_policy(ReusePolicy::Fifo);
+    /// let a = sm.insert(1);
+    /// let b = sm.insert(2);
+    /// sm.remove(a);
+    /// sm.remove(b);
+    ///
+    /// // The slot of a was vacated first, so it is reused first.
+    /// let c = sm.insert(3);
+    /// assert_eq!(sm.get(a), None);
+    /// assert_eq!(sm.iter().next(), Some((c, &3)));
+    /// ```
+    pub fn set_reuse_policy(&mut self, policy: ReusePolicy) {
+        self.policy = policy;
+    }
+
     /// Returns the number of elements in the slot map.
     ///
     /// # Examples
@@ -248,6 +382,50 @@ impl<K: Key, V> SlotMap<K, V> {
         self.num_elems == 0
     }
 
+    /// Returns occupancy statistics of the slot map, useful to judge how
+    /// fragmented it is.
+    ///
+    /// This walks the freelist, so it takes time linear in the number of
+    /// vacant slots.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+    /// for k in &keys[..8] {
+    ///     sm.remove(*k);
+    /// }
+    /// let stats = sm.stats();
+    /// assert_eq!(stats.slots, 10);
+    /// assert_eq!(stats.occupied, 2);
+    /// assert_eq!(stats.vacant, 8);
+    /// assert_eq!(stats.free, 8);
+    /// assert_eq!(stats.max_index, Some(10));
+    /// ```
+    pub fn stats(&self) -> OccupancyStats {
+        // Every slot beyond the sentinel was pushed for a key.
+        let slots = self.slots.len() - 1;
+
+        // Reserved slots are vacant but off the freelist, count it instead.
+        let mut free = 0;
+        let mut cur = self.free_head as usize;
+        while cur < self.slots.len() {
+            free += 1;
+            // This is safe because the freelist only links vacant slots.
+            cur = unsafe { self.slots.get_unchecked(cur).u.next_free } as usize;
+        }
+
+        OccupancyStats {
+            slots,
+            occupied: self.len(),
+            vacant: slots - self.len(),
+            free,
+            max_index: if slots > 0 { Some(slots as u32) } else { None },
+        }
+    }
+
     /// Returns the number of elements the [`SlotMap`] can hold without
     /// reallocating.
     ///
@@ -286,6 +464,34 @@ impl<K: Key, V> SlotMap<K, V> {
         self.slots.reserve(needed);
     }
 
+    /// Reserves the minimum capacity for at least `additional` more elements
+    /// to be inserted in the [`SlotMap`]. Vacant slots that can be reused count
+    /// towards `additional`.
+    ///
+    /// Like [`Vec::reserve_exact`] this does not deliberately over-allocate,
+    /// but the allocator may still give the collection more space than
+    /// requested. Prefer [`reserve`](Self::reserve) if future insertions are
+    /// expected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new allocation size overflows [`usize`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// sm.insert("foo");
+    /// sm.reserve_exact(32);
+    /// assert!(sm.capacity() >= 33);
+    /// ```
+    pub fn reserve_exact(&mut self, additional: usize) {
+        // One slot is reserved for the sentinel.
+        let needed = (self.len() + additional).saturating_sub(self.slots.len() - 1);
+        self.slots.reserve_exact(needed);
+    }
+
     /// Tries to reserve capacity for at least `additional` more elements to be
     /// inserted in the [`SlotMap`]. The collection may reserve more space to
     /// avoid frequent reallocations.
@@ -307,6 +513,128 @@ impl<K: Key, V> SlotMap<K, V> {
         self.slots.try_reserve(needed)
     }
 
+    /// Shrinks the capacity of the slot map as much as possible.
+    ///
+    /// Vacant slots at the end of the storage are released as well. Keys that
+    /// pointed into those slots stay invalid. The slot map remembers the
+    /// highest version it released, and slots that are created again at those
+    /// indices start past that version. Vacant slots followed by an occupied
+    /// slot can not be released, because their versions are still needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::with_capacity(100);
+    /// let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+    /// for k in &keys[10..] {
+    ///     sm.remove(*k);
+    /// }
+    /// sm.shrink_to_fit();
+    /// assert!(sm.capacity() < 100);
+    ///
+    /// let k = sm.insert(42);
+    /// assert_eq!(sm.get(keys[10]), None);
+    /// assert_eq!(sm[k], 42);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        let mut new_len = self.slots.len();
+        let mut released_version = self.slots[0].version;
+        while new_len > 1
+            && !self.slots[new_len - 1].occupied()
+            && !self.slots[new_len - 1].reserved(new_len - 1)
+        {
+            new_len -= 1;
+            released_version = released_version.max(self.slots[new_len].version);
+        }
+
+        if new_len < self.slots.len() {
+            self.slots.truncate(new_len);
+            self.slots[0].version = released_version;
+
+            // The released slots may be anywhere in the freelist, rebuild it.
+            self.rebuild_freelist();
+        }
+
+        self.slots.shrink_to_fit();
+    }
+
+    /// Moves all elements into the lowest slots and releases the storage of
+    /// the vacant slots, so the slot map holds no vacant slots afterwards and
+    /// iterating no longer skips over them.
+    ///
+    /// The key of every moved element changes. For each moved element
+    /// `remap(old_key, new_key, &mut value)` is called so references to it can
+    /// be updated, elements that stay in place are not passed to `remap`.
+    /// After compacting, keys of moved elements are invalid, just like keys
+    /// of removed elements. Slots held by a [`ReservedKey`] stay in place,
+    /// and vacant slots before the last of them can not be released, they
+    /// stay vacant and are reused by later insertions.
+    ///
+    /// This function must iterate over all slots, empty or not.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+    /// for k in &keys[..8] {
+    ///     sm.remove(*k);
+    /// }
+    ///
+    /// let mut moved = Vec::new();
+    /// sm.compact(|old, new, _| moved.push((old, new)));
+    /// assert_eq!(moved.len(), 2);
+    /// assert!(sm.capacity() < 10);
+    /// for (old, new) in moved {
+    ///     assert!(!sm.contains_key(old));
+    ///     assert!(sm.contains_key(new));
+    /// }
+    /// ```
+    pub fn compact<F>(&mut self, mut remap: F)
+    where
+        F: FnMut(K, K, &mut V),
+    {
+        // Move elements from the back into vacant slots at the front, and
+        // collect the moves so remap only runs once the slot map is
+        // consistent again.
+        let mut moves = Vec::new();
+        let mut lo = 1;
+        let mut hi = self.slots.len() - 1;
+        while lo < hi {
+            if self.slots[lo].occupied() || self.slots[lo].reserved(lo) {
+                lo += 1;
+            } else if !self.slots[hi].occupied() {
+                hi -= 1;
+            } else {
+                let (front, back) = self.slots.split_at_mut(hi);
+                let (dst, src) = (&mut front[lo], &mut back[0]);
+                let old_key = KeyData::new(hi as u32, src.version).into();
+                unsafe {
+                    let value = ManuallyDrop::take(&mut src.u.value);
+                    src.u.next_free = 0;
+                    src.version = src.version.wrapping_add(1).max(2);
+                    dst.u.value = ManuallyDrop::new(value);
+                    dst.version |= 1;
+                }
+                moves.push((old_key, lo));
+            }
+        }
+
+        // The slots elements moved into were on the freelist. Rebuild it, then
+        // shrink_to_fit releases the vacant slots at the back and guards their
+        // versions against reuse.
+        self.rebuild_freelist();
+        self.shrink_to_fit();
+
+        for (old_key, idx) in moves {
+            let slot = &mut self.slots[idx];
+            let new_key = KeyData::new(idx as u32, slot.version).into();
+            remap(old_key, new_key, unsafe { &mut *slot.u.value });
+        }
+    }
+
     /// Returns [`true`] if the slot map contains `key`.
     ///
     /// # Examples
@@ -344,7 +672,45 @@ impl<K: Key, V> SlotMap<K, V> {
     /// ```
     #[inline(always)]
     pub fn insert(&mut self, value: V) -> K {
-        unsafe { self.try_insert_with_key::<_, Never>(move |_| Ok(value)).unwrap_unchecked_() }
+        match self.try_insert(value) {
+            Ok(key) => key,
+            Err(_) => panic!("SlotMap number of elements overflow"),
+        }
+    }
+
+    /// Inserts a value into the slot map. Returns a unique key that can be
+    /// used to access this value.
+    ///
+    /// If the number of elements in the slot map equals 2<sup>32</sup> - 2
+    /// the map is full, and the value is handed back in `Err` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// match sm.try_insert(42) {
+    ///     Ok(key) => assert_eq!(sm[key], 42),
+    ///     Err(value) => println!("slot map is full, dropping {}", value),
+    /// }
+    /// ```
+    #[inline(always)]
+    pub fn try_insert(&mut self, value: V) -> Result<K, V> {
+        if self.is_full() {
+            return Err(value);
+        }
+
+        Ok(unsafe { self.try_insert_with_key::<_, Never>(move |_| Ok(value)).unwrap_unchecked_() })
+    }
+
+    // Returns true if inserting another element would exceed the maximum
+    // number of elements.
+    #[inline(always)]
+    fn is_full(&self) -> bool {
+        // Reserved slots are not counted as elements, but do use up indices.
+        self.num_elems + 1 == u32::MAX
+            || (self.free_head as usize == self.slots.len()
+                && self.slots.len() >= u32::MAX as usize)
     }
 
     /// Inserts a value given by `f` into the slot map. The key where the
@@ -363,6 +729,19 @@ impl<K: Key, V> SlotMap<K, V> {
     /// let mut sm = SlotMap::new();
     /// let key = sm.insert_with_key(|k| (k, 20));
     /// assert_eq!(sm[key], (key, 20));
+    ///
+    /// // The key passed to f is the returned key, also when a slot is reused.
+    /// struct Node {
+    ///     me: DefaultKey,
+    ///     parent: Option<DefaultKey>,
+    /// }
+    /// let mut nodes = SlotMap::new();
+    /// let root = nodes.insert_with_key(|me| Node { me, parent: None });
+    /// let child = nodes.insert_with_key(|me| Node { me, parent: Some(root) });
+    /// nodes.remove(child);
+    /// let reused = nodes.insert_with_key(|me| Node { me, parent: Some(root) });
+    /// assert_eq!(nodes[reused].me, reused);
+    /// assert_eq!(nodes[nodes[reused].parent.unwrap()].me, root);
     /// ```
     #[inline(always)]
     pub fn insert_with_key<F>(&mut self, f: F) -> K
@@ -372,6 +751,56 @@ impl<K: Key, V> SlotMap<K, V> {
         unsafe { self.try_insert_with_key::<_, Never>(move |k| Ok(f(k))).unwrap_unchecked_() }
     }
 
+    /// Inserts a value into the slot map, returning its unique key together
+    /// with a mutable reference to the inserted value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of elements in the slot map equals
+    /// 2<sup>32</sup> - 2.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let (key, value) = sm.insert_and_get_mut(vec![1]);
+    /// value.push(2);
+    /// assert_eq!(sm[key], vec![1, 2]);
+    /// ```
+    pub fn insert_and_get_mut(&mut self, value: V) -> (K, &mut V) {
+        let key = self.insert(value);
+        // This is safe because we just inserted the value at key.
+        (key, unsafe { self.get_unchecked_mut(key) })
+    }
+
+    /// Inserts a value given by `f` into the slot map, returning its unique
+    /// key together with a mutable reference to the inserted value. The key
+    /// where the value will be stored is passed into `f`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of elements in the slot map equals
+    /// 2<sup>32</sup> - 2.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let (key, value) = sm.insert_with_key_and_get_mut(|k| (k, 20));
+    /// value.1 += 1;
+    /// assert_eq!(sm[key], (key, 21));
+    /// ```
+    pub fn insert_with_key_and_get_mut<F>(&mut self, f: F) -> (K, &mut V)
+    where
+        F: FnOnce(K) -> V,
+    {
+        let key = self.insert_with_key(f);
+        // This is safe because we just inserted the value at key.
+        (key, unsafe { self.get_unchecked_mut(key) })
+    }
+
     /// Inserts a value given by `f` into the slot map. The key where the
     /// value will be stored is passed into `f`. This is useful to store values
     /// that contain their own key.
@@ -393,15 +822,16 @@ impl<K: Key, V> SlotMap<K, V> {
     ///
     /// sm.try_insert_with_key::<_, ()>(|k| Err(())).unwrap_err();
     /// ```
+    #[inline(always)]
     pub fn try_insert_with_key<F, E>(&mut self, f: F) -> Result<K, E>
     where
         F: FnOnce(K) -> Result<V, E>,
     {
         // In case f panics, we don't make any changes until we have the value.
-        let new_num_elems = self.num_elems + 1;
-        if new_num_elems == core::u32::MAX {
+        if self.is_full() {
             panic!("SlotMap number of elements overflow");
         }
+        let new_num_elems = self.num_elems + 1;
 
         if let Some(slot) = self.slots.get_mut(self.free_head as usize) {
             let occupied_version = slot.version | 1;
@@ -417,10 +847,12 @@ impl<K: Key, V> SlotMap<K, V> {
                 slot.version = occupied_version;
             }
             self.num_elems = new_num_elems;
+            self.max_occupied = self.max_occupied.max(kd.idx);
             return Ok(kd.into());
         }
 
-        let version = 1;
+        // New slots start past any version handed out for a released slot.
+        let version = self.slots[0].version | 1;
         let kd = KeyData::new(self.slots.len() as u32, version);
 
         // Create new slot before adjusting freelist in case f or the allocation panics or errors.
@@ -433,128 +865,669 @@ impl<K: Key, V> SlotMap<K, V> {
 
         self.free_head = kd.idx + 1;
         self.num_elems = new_num_elems;
+        self.max_occupied = kd.idx;
         Ok(kd.into())
     }
 
-    // Helper function to remove a value from a slot. Safe iff the slot is
-    // occupied. Returns the value removed.
-    #[inline(always)]
-    unsafe fn remove_from_slot(&mut self, idx: usize) -> V {
-        // Remove value from slot before overwriting union.
-        let slot = self.slots.get_unchecked_mut(idx);
-        let value = ManuallyDrop::take(&mut slot.u.value);
-
-        // Maintain freelist.
-        slot.u.next_free = self.free_head;
-        self.free_head = idx as u32;
-        self.num_elems -= 1;
-        slot.version = slot.version.wrapping_add(1);
-
-        value
-    }
-
-    /// Removes a key from the slot map, returning the value at the key if the
-    /// key was not previously removed.
+    /// Inserts all values from `iter` into the slot map, returning the keys
+    /// in the same order as the values. Vacant slots are reused exactly as
+    /// they would be by repeated calls to [`insert`](Self::insert), but
+    /// capacity is reserved only once up front.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of elements in the slot map would exceed
+    /// 2<sup>32</sup> - 2.
     ///
     /// # Examples
     ///
     /// ```
     /// # use slotmap::*;
     /// let mut sm = SlotMap::new();
-    /// let key = sm.insert(42);
-    /// assert_eq!(sm.remove(key), Some(42));
-    /// assert_eq!(sm.remove(key), None);
+    /// let keys = sm.insert_from_iter(vec!["a", "b", "c"]);
+    /// assert_eq!(keys.len(), 3);
+    /// assert_eq!(sm[keys[1]], "b");
     /// ```
-    pub fn remove(&mut self, key: K) -> Option<V> {
-        let kd = key.data();
-        if self.contains_key(key) {
-            // This is safe because we know that the slot is occupied.
-            Some(unsafe { self.remove_from_slot(kd.idx as usize) })
-        } else {
-            None
+    pub fn insert_from_iter<I>(&mut self, iter: I) -> Vec<K>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        let mut iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        self.reserve(lower);
+        let mut keys = Vec::with_capacity(lower);
+
+        // Reuse vacant slots first, exactly like insert.
+        while (self.free_head as usize) < self.slots.len() {
+            match iter.next() {
+                Some(value) => keys.push(self.insert(value)),
+                None => return keys,
+            }
+        }
+
+        // The freelist is empty, so the remaining values are pushed into new
+        // slots in one go. Push updates the slot map for them when dropped,
+        // so it stays consistent even if the iterator panics.
+        struct Push<'a, K: Key, V> {
+            sm: &'a mut SlotMap<K, V>,
+            start: u32,
+        }
+
+        impl<'a, K: Key, V> Drop for Push<'a, K, V> {
+            fn drop(&mut self) {
+                let end = self.sm.slots.len() as u32;
+                if end > self.start {
+                    self.sm.free_head = end;
+                    self.sm.num_elems += end - self.start;
+                    self.sm.max_occupied = end - 1;
+                }
+            }
         }
+
+        let version = self.slots[0].version | 1;
+        let start = self.slots.len() as u32;
+        // Both the number of elements and the slot indices are limited.
+        let mut room = (u32::MAX - 1 - self.num_elems).min(u32::MAX - start);
+        let push = Push { sm: self, start };
+        push.sm.slots.extend(iter.map(|value| {
+            if room == 0 {
+                panic!("SlotMap number of elements overflow");
+            }
+            room -= 1;
+            Slot {
+                u: SlotUnion {
+                    value: ManuallyDrop::new(value),
+                },
+                version,
+            }
+        }));
+        let end = push.sm.slots.len() as u32;
+        drop(push);
+
+        keys.extend((start..end).map(|idx| K::from(KeyData::new(idx, version))));
+        keys
     }
 
-    /// Retains only the elements specified by the predicate.
+    /// Moves all elements of `other` into this slot map, calling
+    /// `remap(old_key, new_key)` for every element with its key in `other`
+    /// and its new key in this slot map. Capacity is reserved once up front
+    /// and the values are moved, not cloned.
     ///
-    /// In other words, remove all key-value pairs `(k, v)` such that
-    /// `f(k, &mut v)` returns false. This method invalidates any removed keys.
+    /// # Panics
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// Panics if the number of elements in the slot map would exceed
+    /// 2<sup>32</sup> - 2.
     ///
     /// # Examples
     ///
     /// ```
     /// # use slotmap::*;
-    /// let mut sm = SlotMap::new();
-    ///
-    /// let k1 = sm.insert(0);
-    /// let k2 = sm.insert(1);
-    /// let k3 = sm.insert(2);
-    ///
-    /// sm.retain(|key, val| key == k1 || *val == 1);
+    /// # use std::collections::HashMap;
+    /// let mut world = SlotMap::new();
+    /// world.insert("sun");
     ///
-    /// assert!(sm.contains_key(k1));
-    /// assert!(sm.contains_key(k2));
-    /// assert!(!sm.contains_key(k3));
+    /// let mut chunk = SlotMap::new();
+    /// let tree = chunk.insert("tree");
     ///
-    /// assert_eq!(2, sm.len());
+    /// let mut remapped = HashMap::new();
+    /// world.absorb(chunk, |old, new| {
+    ///     remapped.insert(old, new);
+    /// });
+    /// assert_eq!(world.len(), 2);
+    /// assert_eq!(world[remapped[&tree]], "tree");
     /// ```
-    pub fn retain<F>(&mut self, mut f: F)
+    pub fn absorb<K2, F>(&mut self, other: SlotMap<K2, V>, mut remap: F)
     where
-        F: FnMut(K, &mut V) -> bool,
+        K2: Key,
+        F: FnMut(K2, K),
     {
-        for i in 1..self.slots.len() {
-            // This is safe because removing elements does not shrink slots.
-            let slot = unsafe { self.slots.get_unchecked_mut(i) };
-            let version = slot.version;
-
-            let should_remove = if let OccupiedMut(value) = slot.get_mut() {
-                let key = KeyData::new(i as u32, version).into();
-                !f(key, value)
-            } else {
-                false
-            };
-
-            if should_remove {
-                // This is safe because we know that the slot was occupied.
-                unsafe { self.remove_from_slot(i) };
-            }
+        self.reserve(other.len());
+        for (old_key, value) in other {
+            let new_key = self.insert(value);
+            remap(old_key, new_key);
         }
     }
 
-    /// Clears the slot map. Keeps the allocated memory for reuse.
+    /// Reserves a slot for a value that is inserted later with
+    /// [`insert_reserved`](Self::insert_reserved), and returns the
+    /// reservation. The key the value will have is known right away through
+    /// [`ReservedKey::key`], which allows constructing values that refer to
+    /// each other before inserting them.
+    ///
+    /// Until the reservation is filled the key is not valid: lookups return
+    /// `None`, and the slot is neither counted by [`len`](Self::len) nor
+    /// iterated over.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// # Panics
+    ///
+    /// Panics if the slot map has no more slots available.
     ///
     /// # Examples
     ///
     /// ```
     /// # use slotmap::*;
     /// let mut sm = SlotMap::new();
-    /// for i in 0..10 {
-    ///     sm.insert(i);
-    /// }
-    /// assert_eq!(sm.len(), 10);
-    /// sm.clear();
+    /// let ra = sm.reserve_key();
+    /// let rb = sm.reserve_key();
+    /// let (a, b) = (ra.key(), rb.key());
+    /// assert!(!sm.contains_key(a));
     /// assert_eq!(sm.len(), 0);
+    ///
+    /// assert_eq!(sm.insert_reserved(ra, b), a);
+    /// assert_eq!(sm.insert_reserved(rb, a), b);
+    /// assert_eq!(sm[sm[a]], a);
     /// ```
-    pub fn clear(&mut self) {
-        self.drain();
-    }
+    pub fn reserve_key(&mut self) -> ReservedKey<K> {
+        if let Some(slot) = self.slots.get_mut(self.free_head as usize) {
+            let idx = self.free_head;
+            unsafe {
+                self.free_head = slot.u.next_free;
+                slot.u.next_free = idx;
+            }
+            let key = KeyData::new(idx, slot.version | 1).into();
+            return ReservedKey { key };
+        }
 
-    /// Clears the slot map, returning all key-value pairs in arbitrary order as
-    /// an iterator. Keeps the allocated memory for reuse.
-    ///
+        let idx = self.slots.len();
+        if idx >= u32::MAX as usize {
+            panic!("SlotMap number of elements overflow");
+        }
+
+        // New slots start past any version handed out for a released slot,
+        // and a vacant slot never takes the sentinel's version 0.
+        let version = self.slots[0].version.max(2);
+        self.slots.push(Slot {
+            u: SlotUnion {
+                next_free: idx as u32,
+            },
+            version,
+        });
+        self.free_head = self.slots.len() as u32;
+        let key = KeyData::new(idx as u32, version | 1).into();
+        ReservedKey { key }
+    }
+
+    // Returns the index of the slot held by the reservation, panicking if it
+    // is not a reservation made by this slot map.
+    fn reserved_slot(&self, reserved: &ReservedKey<K>) -> usize {
+        let kd = reserved.key.data();
+        let idx = kd.idx as usize;
+        match self.slots.get(idx) {
+            Some(slot) if idx > 0 && slot.reserved(idx) && slot.version | 1 == kd.version.get() => {
+                idx
+            },
+            _ => panic!("ReservedKey does not belong to this SlotMap"),
+        }
+    }
+
+    /// Fills a slot reserved by [`reserve_key`](Self::reserve_key) with
+    /// `value`, returning its key, which is the key given by
+    /// [`ReservedKey::key`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reservation was not made by this slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let reserved = sm.reserve_key();
+    /// let key = reserved.key();
+    /// assert_eq!(sm.insert_reserved(reserved, 42), key);
+    /// assert_eq!(sm[key], 42);
+    /// ```
+    pub fn insert_reserved(&mut self, reserved: ReservedKey<K>, value: V) -> K {
+        let idx = self.reserved_slot(&reserved);
+        let slot = &mut self.slots[idx];
+        slot.u.value = ManuallyDrop::new(value);
+        slot.version |= 1;
+        self.num_elems += 1;
+        self.max_occupied = self.max_occupied.max(idx as u32);
+        reserved.key
+    }
+
+    /// Cancels a reservation made by [`reserve_key`](Self::reserve_key),
+    /// returning the slot to the slot map for reuse in the order given by its
+    /// [`ReusePolicy`]. The version of the slot is left as is, so the next
+    /// value stored in it gets the key the reservation had.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reservation was not made by this slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let reserved = sm.reserve_key();
+    /// let key = reserved.key();
+    /// sm.cancel_reserved(reserved);
+    /// assert!(!sm.contains_key(key));
+    /// assert_eq!(sm.insert(1), key);
+    /// ```
+    pub fn cancel_reserved(&mut self, reserved: ReservedKey<K>) {
+        let idx = self.reserved_slot(&reserved);
+        // This is safe because reserved_slot checked idx.
+        unsafe { self.push_free(idx) };
+    }
+
+    // Helper function to remove a value from a slot. Safe iff the slot is
+    // occupied. Returns the value removed.
+    #[inline(always)]
+    unsafe fn remove_from_slot(&mut self, idx: usize) -> V {
+        // Remove value from slot before overwriting union.
+        let slot = self.slots.get_unchecked_mut(idx);
+        let value = ManuallyDrop::take(&mut slot.u.value);
+
+        // Version 0 is reserved for the sentinel, skip it when wrapping around.
+        slot.version = slot.version.wrapping_add(1).max(2);
+        self.num_elems -= 1;
+        self.push_free(idx);
+        value
+    }
+
+    // Puts the vacant slot at idx on the freelist, where the reuse policy
+    // decides when it is handed out again. Safe iff idx is in bounds.
+    #[inline(always)]
+    unsafe fn push_free(&mut self, idx: usize) {
+        let end = self.slots.len() as u32;
+        let was_empty = self.free_head == end;
+        match self.policy {
+            ReusePolicy::Lifo => {
+                self.slots.get_unchecked_mut(idx).u.next_free = self.free_head;
+                self.free_head = idx as u32;
+                if was_empty {
+                    self.free_tail = idx as u32;
+                }
+            },
+            ReusePolicy::Fifo => {
+                self.slots.get_unchecked_mut(idx).u.next_free = end;
+                if was_empty {
+                    self.free_head = idx as u32;
+                } else {
+                    let tail = self.free_tail as usize;
+                    self.slots.get_unchecked_mut(tail).u.next_free = idx as u32;
+                }
+                self.free_tail = idx as u32;
+            },
+        }
+    }
+
+    // Lowers max_occupied to the highest slot that is still occupied, or 0 if
+    // there is none. Only scans the vacant slots above it, so this is cheap
+    // unless elements at the top were removed since it was last called.
+    fn lower_max_occupied(&mut self) {
+        while self.max_occupied > 0 && !self.slots[self.max_occupied as usize].occupied() {
+            self.max_occupied -= 1;
+        }
+    }
+
+    // Takes the slot map apart into the version and value of every slot, the
+    // sentinel included. Reserved slots come out as vacant slots.
+    pub(crate) fn into_slot_parts(self) -> impl Iterator<Item = (u32, Option<V>)> {
+        self.slots.into_iter().enumerate().map(|(i, mut slot)| {
+            let version = slot.exported_version(i);
+            let value = if slot.occupied() {
+                // Mark the slot vacant so dropping it doesn't drop the value.
+                slot.version = 0;
+                Some(unsafe { ManuallyDrop::take(&mut slot.u.value) })
+            } else {
+                None
+            };
+            (version, value)
+        })
+    }
+
+    // Builds a slot map from the parts given by into_slot_parts of either
+    // slot map, keeping the index and version of every slot.
+    pub(crate) fn from_slot_parts(parts: impl Iterator<Item = (u32, Option<V>)>) -> Self {
+        let slots: Vec<Slot<V>> = parts
+            .map(|(version, value)| Slot {
+                u: match value {
+                    Some(value) => SlotUnion {
+                        value: ManuallyDrop::new(value),
+                    },
+                    None => SlotUnion { next_free: 0 },
+                },
+                version,
+            })
+            .collect();
+
+        let mut sm = Self {
+            slots,
+            free_head: 0,
+            free_tail: 0,
+            num_elems: 0,
+            max_occupied: 0,
+            policy: ReusePolicy::Lifo,
+            _k: PhantomData,
+        };
+        sm.rebuild_freelist();
+        sm
+    }
+
+    // Rebuilds the freelist, the element count and the highest occupied slot
+    // from the versions of the slots, keeping reserved slots off the
+    // freelist. Any freelist data in other vacant slots is overwritten. The
+    // first slot linked ends up last.
+    fn rebuild_freelist(&mut self) {
+        let end = self.slots.len() as u32;
+        self.free_head = end;
+        self.num_elems = 0;
+        self.max_occupied = 0;
+        for (i, slot) in self.slots.iter_mut().enumerate().skip(1) {
+            if slot.occupied() {
+                self.num_elems += 1;
+                self.max_occupied = i as u32;
+            } else if !slot.reserved(i) {
+                if self.free_head == end {
+                    self.free_tail = i as u32;
+                }
+                slot.u.next_free = self.free_head;
+                self.free_head = i as u32;
+            }
+        }
+    }
+
+    // Panics with a message describing why key is invalid. Kept out of line
+    // so the happy path of indexing stays small.
+    #[cold]
+    #[inline(never)]
+    fn invalid_key_panic(&self, key: K) -> ! {
+        let kd = key.data();
+        match self.key_error(key) {
+            KeyError::Null => panic!("invalid SlotMap key Key({:?}): key is null", kd),
+            KeyError::Stale { current_version } => panic!(
+                "invalid SlotMap key Key({:?}): key is stale, slot currently at version {}",
+                kd, current_version
+            ),
+            _ => panic!(
+                "invalid SlotMap key Key({:?}): key is out of range, slot does not exist",
+                kd
+            ),
+        }
+    }
+
+    // Determines why a key that failed a lookup is invalid.
+    #[cold]
+    fn key_error(&self, key: K) -> KeyError {
+        let kd = key.data();
+        if kd.is_null() {
+            KeyError::Null
+        } else {
+            match self.slots.get(kd.idx as usize) {
+                Some(slot) => KeyError::Stale {
+                    current_version: slot.version,
+                },
+                None => KeyError::OutOfBounds,
+            }
+        }
+    }
+
+    /// Removes a key from the slot map, returning the value at the key if the
+    /// key was not previously removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let key = sm.insert(42);
+    /// assert_eq!(sm.remove(key), Some(42));
+    /// assert_eq!(sm.remove(key), None);
+    /// ```
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let kd = key.data();
+        if self.contains_key(key) {
+            // This is safe because we know that the slot is occupied.
+            Some(unsafe { self.remove_from_slot(kd.idx as usize) })
+        } else {
+            None
+        }
+    }
+
+    /// Removes a key from the slot map if `f` returns true for its value,
+    /// returning the removed value. Returns `None` if the key is invalid or
+    /// `f` returned false, in the latter case the slot map is unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let key = sm.insert(42);
+    /// assert_eq!(sm.remove_if(key, |v| *v < 10), None);
+    /// assert_eq!(sm.remove_if(key, |v| *v > 10), Some(42));
+    /// assert_eq!(sm.remove_if(key, |_| true), None);
+    /// ```
+    pub fn remove_if<F>(&mut self, key: K, f: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> bool,
+    {
+        let idx = key.data().idx as usize;
+        if f(self.get(key)?) {
+            // This is safe because get succeeded, so the slot is occupied.
+            Some(unsafe { self.remove_from_slot(idx) })
+        } else {
+            None
+        }
+    }
+
+    /// Removes all given keys from the slot map, returning how many elements
+    /// were actually removed. Invalid keys, including stale keys and keys that
+    /// appear more than once, are skipped.
+    ///
+    /// The vacated slots are chained together and added to the freelist at
+    /// once. They are reused in the same order as if the keys were removed
+    /// one by one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let a = sm.insert(1);
+    /// let b = sm.insert(2);
+    /// let c = sm.insert(3);
+    /// sm.remove(c);
+    /// assert_eq!(sm.remove_many(&[a, b, a, c]), 2);
+    /// assert!(sm.is_empty());
+    /// ```
+    pub fn remove_many(&mut self, keys: &[K]) -> usize {
+        // Splices the chain of vacated slots into the freelist and updates the
+        // length once, even if dropping a value panics.
+        struct Splice<'a, K: Key, V> {
+            sm: &'a mut SlotMap<K, V>,
+            first: u32,
+            last: u32,
+            count: u32,
+        }
+
+        impl<'a, K: Key, V> Drop for Splice<'a, K, V> {
+            fn drop(&mut self) {
+                if self.count == 0 {
+                    return;
+                }
+
+                let sm = &mut *self.sm;
+                let end = sm.slots.len() as u32;
+                let was_empty = sm.free_head == end;
+                // All unsafe accesses here are safe because the chain and the
+                // freelist tail are in bounds.
+                unsafe {
+                    match sm.policy {
+                        ReusePolicy::Lifo => {
+                            sm.slots.get_unchecked_mut(self.first as usize).u.next_free = sm.free_head;
+                            sm.free_head = self.last;
+                            if was_empty {
+                                sm.free_tail = self.first;
+                            }
+                        },
+                        ReusePolicy::Fifo => {
+                            sm.slots.get_unchecked_mut(self.last as usize).u.next_free = end;
+                            if was_empty {
+                                sm.free_head = self.first;
+                            } else {
+                                let tail = sm.free_tail as usize;
+                                sm.slots.get_unchecked_mut(tail).u.next_free = self.first;
+                            }
+                            sm.free_tail = self.last;
+                        },
+                    }
+                }
+                sm.num_elems -= self.count;
+            }
+        }
+
+        let mut splice = Splice {
+            sm: self,
+            first: 0,
+            last: 0,
+            count: 0,
+        };
+        for &key in keys {
+            if !splice.sm.contains_key(key) {
+                continue;
+            }
+
+            // This is safe because we know that the slot is occupied. A
+            // duplicate key is stale after its slot is vacated. The chain
+            // runs backwards for Lifo, so the last slot vacated is reused
+            // first, and forwards for Fifo, like removing one key at a time.
+            let idx = key.data().idx;
+            let value = unsafe {
+                let slot = splice.sm.slots.get_unchecked_mut(idx as usize);
+                slot.version = slot.version.wrapping_add(1).max(2);
+                let value = ManuallyDrop::take(&mut slot.u.value);
+                match splice.sm.policy {
+                    ReusePolicy::Lifo => slot.u.next_free = splice.last,
+                    ReusePolicy::Fifo if splice.count > 0 => {
+                        let prev = splice.last as usize;
+                        splice.sm.slots.get_unchecked_mut(prev).u.next_free = idx;
+                    },
+                    ReusePolicy::Fifo => {},
+                }
+                value
+            };
+            if splice.count == 0 {
+                splice.first = idx;
+            }
+            splice.last = idx;
+            splice.count += 1;
+            drop(value);
+        }
+
+        splice.count as usize
+    }
+
+    /// Retains only the elements specified by the predicate.
+    ///
+    /// In other words, remove all key-value pairs `(k, v)` such that
+    /// `f(k, &mut v)` returns false. This method invalidates any removed keys.
+    ///
+    /// This function must iterate over all slots up to the highest occupied
+    /// one, empty or not. In the face of many deleted elements it can be
+    /// inefficient.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    ///
+    /// let k1 = sm.insert(0);
+    /// let k2 = sm.insert(1);
+    /// let k3 = sm.insert(2);
+    ///
+    /// sm.retain(|key, val| key == k1 || *val == 1);
+    ///
+    /// assert!(sm.contains_key(k1));
+    /// assert!(sm.contains_key(k2));
+    /// assert!(!sm.contains_key(k3));
+    ///
+    /// assert_eq!(2, sm.len());
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(K, &mut V) -> bool,
+    {
+        // Stop as soon as every element was visited, the remaining slots are
+        // all vacant.
+        let mut num_left = self.num_elems;
+        let mut max_kept = 0;
+        let mut i = 1;
+        while num_left > 0 {
+            // This is safe because removing elements does not shrink slots and
+            // there are occupied slots left.
+            let slot = unsafe { self.slots.get_unchecked_mut(i) };
+            let version = slot.version;
+
+            let should_remove = if let OccupiedMut(value) = slot.get_mut() {
+                num_left -= 1;
+                let key = KeyData::new(i as u32, version).into();
+                let keep = f(key, value);
+                if keep {
+                    max_kept = i;
+                }
+                !keep
+            } else {
+                false
+            };
+
+            if should_remove {
+                // This is safe because we know that the slot was occupied.
+                unsafe { self.remove_from_slot(i) };
+            }
+            i += 1;
+        }
+
+        // Every element was visited, so the highest one kept is known.
+        self.max_occupied = max_kept as u32;
+    }
+
+    /// Clears the slot map. Keeps the allocated memory for reuse.
+    ///
+    /// Every cleared slot has its version bumped, so all keys handed out before
+    /// clearing stay invalid, even after their slots are reused.
+    ///
+    /// This function must iterate over all slots up to the highest occupied
+    /// one, empty or not. In the face of many deleted elements it can be
+    /// inefficient.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// for i in 0..10 {
+    ///     sm.insert(i);
+    /// }
+    /// let old = sm.insert(10);
+    /// assert_eq!(sm.len(), 11);
+    /// sm.clear();
+    /// assert_eq!(sm.len(), 0);
+    ///
+    /// sm.insert(42);
+    /// assert_eq!(sm.get(old), None);
+    /// ```
+    pub fn clear(&mut self) {
+        self.drain();
+    }
+
+    /// Clears the slot map, returning all key-value pairs in ascending slot
+    /// order as an iterator. Keeps the allocated memory for reuse.
+    ///
     /// When the iterator is dropped all elements in the slot map are removed,
     /// even if the iterator was not fully consumed. If the iterator is not
     /// dropped (using e.g. [`std::mem::forget`]), only the elements that were
     /// iterated over are removed.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// This function must iterate over all slots up to the highest occupied
+    /// one, empty or not. In the face of many deleted elements it can be
+    /// inefficient.
     ///
     /// # Examples
     ///
@@ -570,6 +1543,45 @@ impl<K: Key, V> SlotMap<K, V> {
         Drain { cur: 1, sm: self }
     }
 
+    /// Creates an iterator which uses `f` to decide if an element should be
+    /// removed. If `f(k, &mut v)` returns true the key-value pair is removed
+    /// from the slot map and yielded, otherwise it stays in place. This method
+    /// invalidates any removed keys.
+    ///
+    /// Elements are only removed as the iterator advances. If the iterator is
+    /// dropped before it is fully consumed, the elements it has not visited yet
+    /// stay in the slot map, whether or not `f` would have selected them.
+    ///
+    /// This function must iterate over all slots up to the highest occupied
+    /// one, empty or not. In the face of many deleted elements it can be
+    /// inefficient.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let k1 = sm.insert(1);
+    /// let k2 = sm.insert(2);
+    /// let k3 = sm.insert(3);
+    ///
+    /// let odd: Vec<_> = sm.drain_filter(|_, v| *v % 2 == 1).collect();
+    /// assert_eq!(odd, vec![(k1, 1), (k3, 3)]);
+    /// assert_eq!(sm.len(), 1);
+    /// assert_eq!(sm[k2], 2);
+    /// ```
+    pub fn drain_filter<F>(&mut self, f: F) -> DrainFilter<'_, K, V, F>
+    where
+        F: FnMut(K, &mut V) -> bool,
+    {
+        self.lower_max_occupied();
+        DrainFilter {
+            sm: self,
+            cur: 1,
+            f,
+        }
+    }
+
     /// Returns a reference to the value corresponding to the key.
     ///
     /// # Examples
@@ -590,6 +1602,27 @@ impl<K: Key, V> SlotMap<K, V> {
             .map(|slot| unsafe { &*slot.u.value })
     }
 
+    /// Returns a reference to the value corresponding to the key, or a
+    /// [`KeyError`] describing why the key is not valid in this slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let key = sm.insert("bar");
+    /// assert_eq!(sm.try_get(key), Ok(&"bar"));
+    /// sm.remove(key);
+    /// assert!(matches!(sm.try_get(key), Err(KeyError::Stale { .. })));
+    /// assert_eq!(sm.try_get(DefaultKey::null()), Err(KeyError::Null));
+    /// ```
+    pub fn try_get(&self, key: K) -> Result<&V, KeyError> {
+        match self.get(key) {
+            Some(value) => Ok(value),
+            None => Err(self.key_error(key)),
+        }
+    }
+
     /// Returns a reference to the value corresponding to the key without
     /// version or bounds checking.
     ///
@@ -634,6 +1667,31 @@ impl<K: Key, V> SlotMap<K, V> {
             .map(|slot| unsafe { &mut *slot.u.value })
     }
 
+    /// Returns a mutable reference to the value corresponding to the key, or a
+    /// [`KeyError`] describing why the key is not valid in this slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let key = sm.insert(3.5);
+    /// if let Ok(x) = sm.try_get_mut(key) {
+    ///     *x += 3.0;
+    /// }
+    /// assert_eq!(sm[key], 6.5);
+    /// sm.remove(key);
+    /// let err = sm.try_get_mut(key).unwrap_err();
+    /// assert_eq!(err.to_string(), "key is stale, slot is currently at version 2");
+    /// ```
+    pub fn try_get_mut(&mut self, key: K) -> Result<&mut V, KeyError> {
+        if self.contains_key(key) {
+            Ok(unsafe { self.get_unchecked_mut(key) })
+        } else {
+            Err(self.key_error(key))
+        }
+    }
+
     /// Returns a mutable reference to the value corresponding to the key
     /// without version or bounds checking.
     ///
@@ -658,6 +1716,206 @@ impl<K: Key, V> SlotMap<K, V> {
         &mut self.slots.get_unchecked_mut(key.data().idx as usize).u.value
     }
 
+    /// Returns the key and a reference to the value stored in the slot with
+    /// index `idx`, or `None` if that slot is vacant or does not exist. The
+    /// returned key carries the current version of the slot.
+    ///
+    /// This allows side structures to store bare slot indices, as returned by
+    /// [`KeyData::index`], and resolve them later.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let key = sm.insert("foo");
+    /// let idx = key.data().index();
+    /// assert_eq!(sm.get_by_index(idx), Some((key, &"foo")));
+    /// sm.remove(key);
+    /// assert_eq!(sm.get_by_index(idx), None);
+    /// assert_eq!(sm.get_by_index(1000), None);
+    /// ```
+    pub fn get_by_index(&self, idx: u32) -> Option<(K, &V)> {
+        match self.slots.get(idx as usize)?.get() {
+            Occupied(value) => {
+                let version = self.slots[idx as usize].version;
+                Some((KeyData::new(idx, version).into(), value))
+            },
+            Vacant(_) => None,
+        }
+    }
+
+    /// Returns the key and a mutable reference to the value stored in the
+    /// slot with index `idx`, or `None` if that slot is vacant or does not
+    /// exist. The returned key carries the current version of the slot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let key = sm.insert(1);
+    /// if let Some((k, v)) = sm.get_by_index_mut(1) {
+    ///     assert_eq!(k, key);
+    ///     *v += 1;
+    /// }
+    /// assert_eq!(sm[key], 2);
+    /// ```
+    pub fn get_by_index_mut(&mut self, idx: u32) -> Option<(K, &mut V)> {
+        let slot = self.slots.get_mut(idx as usize)?;
+        let version = slot.version;
+        match slot.get_mut() {
+            OccupiedMut(value) => Some((KeyData::new(idx, version).into(), value)),
+            VacantMut(_) => None,
+        }
+    }
+
+    /// Returns the key of the value behind the given reference, if that
+    /// reference points to a value stored in this slot map. Returns `None` for
+    /// references to anything else, such as a value in another slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let a = sm.insert(1);
+    /// let b = sm.insert(2);
+    /// let mut to_remove = Vec::new();
+    /// for v in sm.values() {
+    ///     if *v == 2 {
+    ///         to_remove.push(sm.key_of(v).unwrap());
+    ///     }
+    /// }
+    /// assert_eq!(to_remove, vec![b]);
+    /// assert_eq!(sm.key_of(&2), None);
+    /// ```
+    pub fn key_of(&self, value: &V) -> Option<K> {
+        // Values live at the start of the slot union, so the distance to the
+        // union of the sentinel slot is a multiple of the slot size.
+        let base: *const SlotUnion<V> = &self.slots[0].u;
+        let ptr: *const V = value;
+        let offset = (ptr as usize).wrapping_sub(base as usize);
+        let stride = core::mem::size_of::<Slot<V>>();
+        let idx = offset / stride;
+        if idx * stride != offset {
+            return None;
+        }
+
+        match self.slots.get(idx) {
+            Some(slot) if idx > 0 && slot.occupied() => {
+                Some(KeyData::new(idx as u32, slot.version).into())
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns mutable references to the values corresponding to the two given
+    /// keys. Both keys must be valid and refer to different slots, otherwise
+    /// None is returned.
+    ///
+    /// Unlike [`get_disjoint_mut`](Self::get_disjoint_mut) this does not
+    /// require const generics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let ka = sm.insert(1);
+    /// let kb = sm.insert(2);
+    /// assert_eq!(sm.get2_mut(ka, ka), None); // Not disjoint.
+    /// if let Some((a, b)) = sm.get2_mut(ka, kb) {
+    ///     *a += 10;
+    ///     *b += 20;
+    /// }
+    /// assert_eq!(sm[ka], 11);
+    /// assert_eq!(sm[kb], 22);
+    /// ```
+    pub fn get2_mut(&mut self, a: K, b: K) -> Option<(&mut V, &mut V)> {
+        // Comparing slot indices rather than keys ensures two versions of the
+        // same slot can never alias, even though only one can be valid.
+        if a.data().idx == b.data().idx || !self.contains_key(a) || !self.contains_key(b) {
+            return None;
+        }
+
+        // Derive both references from a single raw pointer so that creating
+        // the second one does not invalidate the first.
+        unsafe {
+            let slots = self.slots.as_mut_ptr();
+            let va = &mut *(*slots.add(a.data().idx as usize)).u.value;
+            let vb = &mut *(*slots.add(b.data().idx as usize)).u.value;
+            Some((va, vb))
+        }
+    }
+
+    /// Swaps the values corresponding to the two given keys, returning `true`
+    /// if both keys are valid. Keys are left untouched, so afterwards `a`
+    /// refers to the old value of `b` and vice versa. Swapping a valid key with
+    /// itself does nothing and returns `true`.
+    ///
+    /// If either key is invalid nothing is swapped and `false` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let ka = sm.insert("a");
+    /// let kb = sm.insert("b");
+    /// assert!(sm.swap(ka, kb));
+    /// assert_eq!(sm[ka], "b");
+    /// assert_eq!(sm[kb], "a");
+    /// sm.remove(kb);
+    /// assert!(!sm.swap(ka, kb));
+    /// ```
+    pub fn swap(&mut self, a: K, b: K) -> bool {
+        if a == b {
+            return self.contains_key(a);
+        }
+
+        match self.get2_mut(a, b) {
+            Some((va, vb)) => {
+                core::mem::swap(va, vb);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns references to the values corresponding to the given keys. All
+    /// keys must be valid, otherwise None is returned. Unlike
+    /// [`get_disjoint_mut`](Self::get_disjoint_mut) the same key may be given
+    /// more than once.
+    ///
+    /// Requires at least stable Rust version 1.51.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let ka = sm.insert("butter");
+    /// let kb = sm.insert("apples");
+    /// let kc = sm.insert("charlie");
+    /// sm.remove(kc); // Make key c invalid.
+    /// assert_eq!(sm.get_many([ka, kb, kc]), None); // Has invalid key.
+    /// assert_eq!(sm.get_many([ka, kb, ka]), Some([&"butter", &"apples", &"butter"]));
+    /// ```
+    #[cfg(has_min_const_generics)]
+    pub fn get_many<const N: usize>(&self, keys: [K; N]) -> Option<[&V; N]> {
+        // Create an uninitialized array of `MaybeUninit`. The `assume_init` is
+        // safe because the type we are claiming to have initialized here is a
+        // bunch of `MaybeUninit`s, which do not require initialization.
+        let mut refs: [MaybeUninit<&V>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        for (r, key) in refs.iter_mut().zip(keys.iter()) {
+            *r = MaybeUninit::new(self.get(*key)?);
+        }
+
+        // All were valid, so every element was initialized.
+        Some(unsafe { core::mem::transmute_copy::<_, [&V; N]>(&refs) })
+    }
+
     /// Returns mutable references to the values corresponding to the given
     /// keys. All keys must be valid and disjoint, otherwise None is returned.
     ///
@@ -755,11 +2013,15 @@ impl<K: Key, V> SlotMap<K, V> {
         core::mem::transmute_copy::<_, [&mut V; N]>(&ptrs)
     }
 
-    /// An iterator visiting all key-value pairs in arbitrary order. The
+    /// An iterator visiting all key-value pairs in ascending slot order. The
     /// iterator element type is `(K, &'a V)`.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// Empty slots below the highest occupied one are visited too, from either
+    /// end, so in the face of many deleted elements this can be inefficient.
+    /// Removing elements keeps removal cheap by not looking for the new highest
+    /// occupied slot. Iterating from the back also visits the slots vacated at
+    /// the top, until a mutable iteration such as [`retain`](Self::retain)
+    /// finds it again.
     ///
     /// # Examples
     ///
@@ -775,7 +2037,7 @@ impl<K: Key, V> SlotMap<K, V> {
     /// }
     /// ```
     pub fn iter(&self) -> Iter<K, V> {
-        let mut it = self.slots.iter().enumerate();
+        let mut it = self.slots[..=self.max_occupied as usize].iter().enumerate();
         it.next(); // Skip sentinel.
         Iter {
             slots: it,
@@ -784,12 +2046,12 @@ impl<K: Key, V> SlotMap<K, V> {
         }
     }
 
-    /// An iterator visiting all key-value pairs in arbitrary order, with
+    /// An iterator visiting all key-value pairs in ascending slot order, with
     /// mutable references to the values. The iterator element type is
     /// `(K, &'a mut V)`.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// Empty slots below the highest occupied one are visited too, from either
+    /// end, so in the face of many deleted elements this can be inefficient.
     ///
     /// # Examples
     ///
@@ -811,8 +2073,10 @@ impl<K: Key, V> SlotMap<K, V> {
     /// assert_eq!(sm[k2], -30);
     /// ```
     pub fn iter_mut(&mut self) -> IterMut<K, V> {
+        self.lower_max_occupied();
         let len = self.len();
-        let mut it = self.slots.iter_mut().enumerate();
+        let max = self.max_occupied as usize;
+        let mut it = self.slots[..=max].iter_mut().enumerate();
         it.next(); // Skip sentinel.
         IterMut {
             num_left: len,
@@ -821,11 +2085,11 @@ impl<K: Key, V> SlotMap<K, V> {
         }
     }
 
-    /// An iterator visiting all keys in arbitrary order. The iterator element
-    /// type is `K`.
+    /// An iterator visiting all keys in ascending slot order. The iterator
+    /// element type is `K`.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// Empty slots below the highest occupied one are visited too, from either
+    /// end, so in the face of many deleted elements this can be inefficient.
     ///
     /// # Examples
     ///
@@ -839,16 +2103,23 @@ impl<K: Key, V> SlotMap<K, V> {
     /// let keys: HashSet<_> = sm.keys().collect();
     /// let check: HashSet<_> = vec![k0, k1, k2].into_iter().collect();
     /// assert_eq!(keys, check);
+    ///
+    /// // Collect keys first to remove a subset afterwards.
+    /// let to_remove: Vec<_> = sm.keys().filter(|&k| k != k1).collect();
+    /// for k in to_remove {
+    ///     sm.remove(k);
+    /// }
+    /// assert_eq!(sm.keys().collect::<Vec<_>>(), vec![k1]);
     /// ```
     pub fn keys(&self) -> Keys<K, V> {
         Keys { inner: self.iter() }
     }
 
-    /// An iterator visiting all values in arbitrary order. The iterator element
-    /// type is `&'a V`.
+    /// An iterator visiting all values in ascending slot order. The iterator
+    /// element type is `&'a V`.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// Empty slots below the highest occupied one are visited too, from either
+    /// end, so in the face of many deleted elements this can be inefficient.
     ///
     /// # Examples
     ///
@@ -867,11 +2138,11 @@ impl<K: Key, V> SlotMap<K, V> {
         Values { inner: self.iter() }
     }
 
-    /// An iterator visiting all values mutably in arbitrary order. The iterator
-    /// element type is `&'a mut V`.
+    /// An iterator visiting all values mutably in ascending slot order. The
+    /// iterator element type is `&'a mut V`.
     ///
-    /// This function must iterate over all slots, empty or not. In the face of
-    /// many deleted elements it can be inefficient.
+    /// Empty slots below the highest occupied one are visited too, from either
+    /// end, so in the face of many deleted elements this can be inefficient.
     ///
     /// # Examples
     ///
@@ -879,18 +2150,206 @@ impl<K: Key, V> SlotMap<K, V> {
     /// # use slotmap::*;
     /// # use std::collections::HashSet;
     /// let mut sm = SlotMap::new();
-    /// sm.insert(1);
+    /// let k1 = sm.insert(1);
     /// sm.insert(2);
     /// sm.insert(3);
     /// sm.values_mut().for_each(|n| { *n *= 3 });
+    /// assert_eq!(sm.get(k1), Some(&3));
     /// let values: HashSet<_> = sm.into_iter().map(|(_k, v)| v).collect();
     /// let check: HashSet<_> = vec![3, 6, 9].into_iter().collect();
     /// assert_eq!(values, check);
     /// ```
-    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
-        ValuesMut {
-            inner: self.iter_mut(),
+    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+
+    /// Creates a consuming iterator visiting all keys in ascending slot order.
+    /// The slot map cannot be used after calling this. The iterator element
+    /// type is `K`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let k0 = sm.insert(10);
+    /// let k1 = sm.insert(20);
+    /// let keys: Vec<_> = sm.into_keys().collect();
+    /// assert_eq!(keys, vec![k0, k1]);
+    /// ```
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    /// Creates a consuming iterator visiting all values in ascending slot
+    /// order. The slot map cannot be used after calling this. The iterator
+    /// element type is `V`.
+    ///
+    /// Values that have not been yielded are dropped together with the
+    /// iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// sm.insert(String::from("foo"));
+    /// sm.insert(String::from("bar"));
+    /// let values: Vec<_> = sm.into_values().collect();
+    /// assert_eq!(values, vec!["foo", "bar"]);
+    /// ```
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+
+    /// Creates a new slot map with exactly the same keys as this one, where
+    /// every value is computed from the original by `f`. Every key that is
+    /// valid in `self` is valid in the returned slot map, and keys removed
+    /// from `self` stay invalid.
+    ///
+    /// The slots, versions and freelist are copied over, so both slot maps
+    /// also hand out the same keys for future insertions, as long as they see
+    /// the same operations. The exception are slots held by a
+    /// [`ReservedKey`]: they are vacant in the returned slot map, a version
+    /// further so the reserved key stays invalid there, and are added to its
+    /// freelist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut names = SlotMap::new();
+    /// let a = names.insert("alice");
+    /// let b = names.insert("bob");
+    /// names.remove(a);
+    ///
+    /// let mut lens = names.map_values(|_, name| name.len());
+    /// assert_eq!(lens[b], 3);
+    /// assert!(!lens.contains_key(a));
+    /// assert_eq!(lens.insert(0), names.insert(""));
+    /// ```
+    pub fn map_values<U, F>(&self, mut f: F) -> SlotMap<K, U>
+    where
+        F: FnMut(K, &V) -> U,
+    {
+        let mut slots = Vec::with_capacity(self.slots.len());
+        let mut reserved = Vec::new();
+        for (idx, slot) in self.slots.iter().enumerate() {
+            let u = match slot.get() {
+                Occupied(value) => {
+                    let key = KeyData::new(idx as u32, slot.version).into();
+                    SlotUnion {
+                        value: ManuallyDrop::new(f(key, value)),
+                    }
+                },
+                Vacant(&next_free) => {
+                    if idx > 0 && slot.reserved(idx) {
+                        reserved.push(idx);
+                    }
+                    SlotUnion { next_free }
+                },
+            };
+            slots.push(Slot {
+                u,
+                version: slot.exported_version(idx),
+            });
+        }
+
+        let mut sm = SlotMap {
+            slots,
+            free_head: self.free_head,
+            free_tail: self.free_tail,
+            num_elems: self.num_elems,
+            max_occupied: self.max_occupied,
+            policy: self.policy,
+            _k: PhantomData,
+        };
+        for idx in reserved {
+            // This is safe because idx is a vacant slot not on the freelist.
+            unsafe { sm.push_free(idx) };
+        }
+        sm
+    }
+
+    /// Verifies the internal consistency of the slot map, returning the first
+    /// inconsistency found. This takes time linear in the number of slots and
+    /// is intended for testing and fuzzing code built on top of the slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = SlotMap::new();
+    /// let k = sm.insert(1);
+    /// sm.insert(2);
+    /// sm.remove(k);
+    /// assert_eq!(sm.check_invariants(), Ok(()));
+    /// ```
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        if self.slots[0].occupied() {
+            return Err(InvariantError::SentinelOccupied);
+        }
+
+        // Walk the freelist, which ends at slots.len().
+        let mut on_freelist = alloc::vec![false; self.slots.len()];
+        let mut last = None;
+        let mut cur = self.free_head;
+        while cur as usize != self.slots.len() {
+            let slot = match self.slots.get(cur as usize) {
+                Some(slot) if cur > 0 => slot,
+                _ => return Err(InvariantError::FreeListOutOfBounds { idx: cur }),
+            };
+            if slot.occupied() {
+                return Err(InvariantError::FreeListOccupied { idx: cur });
+            }
+            if on_freelist[cur as usize] {
+                return Err(InvariantError::FreeListCycle);
+            }
+            on_freelist[cur as usize] = true;
+            last = Some(cur);
+            cur = unsafe { slot.u.next_free };
+        }
+
+        if last.is_some() && last != Some(self.free_tail) {
+            return Err(InvariantError::FreeListTail {
+                idx: self.free_tail,
+            });
+        }
+
+        let mut occupied = 0;
+        let mut max_occupied = 0;
+        for (i, slot) in self.slots.iter().enumerate().skip(1) {
+            if slot.version == 0 {
+                return Err(InvariantError::ZeroVersion { idx: i as u32 });
+            }
+            if slot.occupied() {
+                occupied += 1;
+                max_occupied = i as u32;
+            } else if !on_freelist[i] && !slot.reserved(i) {
+                return Err(InvariantError::UnreachableVacant { idx: i as u32 });
+            }
+        }
+
+        if max_occupied > self.max_occupied || self.max_occupied as usize >= self.slots.len() {
+            return Err(InvariantError::MaxOccupied {
+                idx: self.max_occupied,
+            });
+        }
+
+        if occupied != self.len() {
+            return Err(InvariantError::LenMismatch {
+                len: self.len(),
+                occupied,
+            });
         }
+
+        Ok(())
     }
 }
 
@@ -908,7 +2367,16 @@ where
     fn clone_from(&mut self, source: &Self) {
         self.slots.clone_from(&source.slots);
         self.free_head = source.free_head;
+        self.free_tail = source.free_tail;
         self.num_elems = source.num_elems;
+        self.max_occupied = source.max_occupied;
+        self.policy = source.policy;
+    }
+}
+
+impl<K: Key, V: fmt::Debug> fmt::Debug for SlotMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
@@ -918,23 +2386,52 @@ impl<K: Key, V> Default for SlotMap<K, V> {
     }
 }
 
+/// Two slot maps are equal if they contain the same key-value pairs. Vacant
+/// slots and the order of the free list are not taken into account.
+impl<K: Key, V: PartialEq> PartialEq for SlotMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+
+        self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Key, V: Eq> Eq for SlotMap<K, V> {}
+
+/// Collects the values into a freshly created [`SlotMap`]. The values are
+/// given keys in the order they are yielded.
+impl<K: Key, V> FromIterator<V> for SlotMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut sm = Self::with_capacity_and_key(iter.size_hint().0);
+        for value in iter {
+            sm.insert(value);
+        }
+        sm
+    }
+}
+
 impl<K: Key, V> Index<K> for SlotMap<K, V> {
     type Output = V;
 
     fn index(&self, key: K) -> &V {
         match self.get(key) {
             Some(r) => r,
-            None => panic!("invalid SlotMap key used"),
+            None => self.invalid_key_panic(key),
         }
     }
 }
 
 impl<K: Key, V> IndexMut<K> for SlotMap<K, V> {
     fn index_mut(&mut self, key: K) -> &mut V {
-        match self.get_mut(key) {
-            Some(r) => r,
-            None => panic!("invalid SlotMap key used"),
+        if !self.contains_key(key) {
+            self.invalid_key_panic(key);
         }
+
+        // Safe, we just checked that the key is valid.
+        unsafe { self.get_unchecked_mut(key) }
     }
 }
 
@@ -942,27 +2439,51 @@ impl<K: Key, V> IndexMut<K> for SlotMap<K, V> {
 /// A draining iterator for [`SlotMap`].
 ///
 /// This iterator is created by [`SlotMap::drain`].
-#[derive(Debug)]
 pub struct Drain<'a, K: 'a + Key, V: 'a> {
     sm: &'a mut SlotMap<K, V>,
     cur: usize,
 }
 
+/// A draining iterator for [`SlotMap`] that only removes the elements
+/// selected by a predicate.
+///
+/// This iterator is created by [`SlotMap::drain_filter`].
+pub struct DrainFilter<'a, K: 'a + Key, V: 'a, F> {
+    sm: &'a mut SlotMap<K, V>,
+    cur: usize,
+    f: F,
+}
+
 /// An iterator that moves key-value pairs out of a [`SlotMap`].
 ///
 /// This iterator is created by calling the `into_iter` method on [`SlotMap`],
 /// provided by the [`IntoIterator`] trait.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct IntoIter<K: Key, V> {
     num_left: usize,
     slots: Enumerate<alloc::vec::IntoIter<Slot<V>>>,
     _k: PhantomData<fn(K) -> K>,
 }
 
+/// An iterator that moves keys out of a [`SlotMap`].
+///
+/// This iterator is created by [`SlotMap::into_keys`].
+#[derive(Clone)]
+pub struct IntoKeys<K: Key, V> {
+    inner: IntoIter<K, V>,
+}
+
+/// An iterator that moves values out of a [`SlotMap`].
+///
+/// This iterator is created by [`SlotMap::into_values`].
+#[derive(Clone)]
+pub struct IntoValues<K: Key, V> {
+    inner: IntoIter<K, V>,
+}
+
 /// An iterator over the key-value pairs in a [`SlotMap`].
 ///
 /// This iterator is created by [`SlotMap::iter`].
-#[derive(Debug)]
 pub struct Iter<'a, K: 'a + Key, V: 'a> {
     num_left: usize,
     slots: Enumerate<core::slice::Iter<'a, Slot<V>>>,
@@ -982,7 +2503,6 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Iter<'a, K, V> {
 /// A mutable iterator over the key-value pairs in a [`SlotMap`].
 ///
 /// This iterator is created by [`SlotMap::iter_mut`].
-#[derive(Debug)]
 pub struct IterMut<'a, K: 'a + Key, V: 'a> {
     num_left: usize,
     slots: Enumerate<core::slice::IterMut<'a, Slot<V>>>,
@@ -992,7 +2512,6 @@ pub struct IterMut<'a, K: 'a + Key, V: 'a> {
 /// An iterator over the keys in a [`SlotMap`].
 ///
 /// This iterator is created by [`SlotMap::keys`].
-#[derive(Debug)]
 pub struct Keys<'a, K: 'a + Key, V: 'a> {
     inner: Iter<'a, K, V>,
 }
@@ -1008,7 +2527,6 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Keys<'a, K, V> {
 /// An iterator over the values in a [`SlotMap`].
 ///
 /// This iterator is created by [`SlotMap::values`].
-#[derive(Debug)]
 pub struct Values<'a, K: 'a + Key, V: 'a> {
     inner: Iter<'a, K, V>,
 }
@@ -1024,17 +2542,97 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Values<'a, K, V> {
 /// A mutable iterator over the values in a [`SlotMap`].
 ///
 /// This iterator is created by [`SlotMap::values_mut`].
-#[derive(Debug)]
 pub struct ValuesMut<'a, K: 'a + Key, V: 'a> {
     inner: IterMut<'a, K, V>,
 }
 
+impl<'a, K: Key, V: fmt::Debug> fmt::Debug for Drain<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let remaining = self.sm.slots[self.cur..]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| match slot.get() {
+                Occupied(value) => {
+                    let key: K = KeyData::new((self.cur + i) as u32, slot.version).into();
+                    Some((key, value))
+                },
+                Vacant(_) => None,
+            });
+        f.debug_list().entries(remaining).finish()
+    }
+}
+
+impl<'a, K: Key, V, F> fmt::Debug for DrainFilter<'a, K, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrainFilter").finish()
+    }
+}
+
+impl<K: Key, V> fmt::Debug for IntoIter<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("remaining", &self.num_left)
+            .finish()
+    }
+}
+
+impl<K: Key, V> fmt::Debug for IntoKeys<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoKeys")
+            .field("remaining", &self.inner.num_left)
+            .finish()
+    }
+}
+
+impl<K: Key, V> fmt::Debug for IntoValues<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoValues")
+            .field("remaining", &self.inner.num_left)
+            .finish()
+    }
+}
+
+impl<'a, K: Key, V: fmt::Debug> fmt::Debug for Iter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a, K: Key, V> fmt::Debug for IterMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IterMut")
+            .field("remaining", &self.num_left)
+            .finish()
+    }
+}
+
+impl<'a, K: Key, V> fmt::Debug for Keys<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a, K: Key, V: fmt::Debug> fmt::Debug for Values<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a, K: Key, V> fmt::Debug for ValuesMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ValuesMut")
+            .field("remaining", &self.inner.num_left)
+            .finish()
+    }
+}
+
 impl<'a, K: Key, V> Iterator for Drain<'a, K, V> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<(K, V)> {
-        let len = self.sm.slots.len();
-        while self.cur < len {
+        // Stop as soon as the slot map is empty, max_occupied may be past the
+        // last element.
+        while self.sm.num_elems > 0 && self.cur <= self.sm.max_occupied as usize {
             let idx = self.cur;
             self.cur += 1;
 
@@ -1059,6 +2657,38 @@ impl<'a, K: Key, V> Iterator for Drain<'a, K, V> {
 impl<'a, K: Key, V> Drop for Drain<'a, K, V> {
     fn drop(&mut self) {
         self.for_each(|_drop| {});
+        self.sm.max_occupied = 0;
+    }
+}
+
+impl<'a, K: Key, V, F> Iterator for DrainFilter<'a, K, V, F>
+where
+    F: FnMut(K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        while self.cur <= self.sm.max_occupied as usize {
+            let idx = self.cur;
+            self.cur += 1;
+
+            // This is safe because removing doesn't shrink slots.
+            let slot = unsafe { self.sm.slots.get_unchecked_mut(idx) };
+            let version = slot.version;
+            if let OccupiedMut(value) = slot.get_mut() {
+                let key = KeyData::new(idx as u32, version).into();
+                if (self.f)(key, value) {
+                    // This is safe because we know that the slot was occupied.
+                    return Some((key, unsafe { self.sm.remove_from_slot(idx) }));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.sm.len()))
     }
 }
 
@@ -1066,6 +2696,11 @@ impl<K: Key, V> Iterator for IntoIter<K, V> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<(K, V)> {
+        // All remaining slots are vacant, no need to scan them.
+        if self.num_left == 0 {
+            return None;
+        }
+
         while let Some((idx, mut slot)) = self.slots.next() {
             if slot.occupied() {
                 let kd = KeyData::new(idx as u32, slot.version);
@@ -1089,10 +2724,39 @@ impl<K: Key, V> Iterator for IntoIter<K, V> {
     }
 }
 
+impl<K: Key, V> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: Key, V> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
 impl<'a, K: Key, V> Iterator for Iter<'a, K, V> {
     type Item = (K, &'a V);
 
     fn next(&mut self) -> Option<(K, &'a V)> {
+        // All remaining slots are vacant, no need to scan them.
+        if self.num_left == 0 {
+            return None;
+        }
+
         while let Some((idx, slot)) = self.slots.next() {
             if let Occupied(value) = slot.get() {
                 let kd = KeyData::new(idx as u32, slot.version);
@@ -1113,6 +2777,11 @@ impl<'a, K: Key, V> Iterator for IterMut<'a, K, V> {
     type Item = (K, &'a mut V);
 
     fn next(&mut self) -> Option<(K, &'a mut V)> {
+        // All remaining slots are vacant, no need to scan them.
+        if self.num_left == 0 {
+            return None;
+        }
+
         while let Some((idx, slot)) = self.slots.next() {
             let version = slot.version;
             if let OccupiedMut(value) = slot.get_mut() {
@@ -1166,6 +2835,63 @@ impl<'a, K: Key, V> Iterator for ValuesMut<'a, K, V> {
     }
 }
 
+impl<'a, K: Key, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, &'a V)> {
+        // All remaining slots are vacant, no need to scan them.
+        if self.num_left == 0 {
+            return None;
+        }
+
+        while let Some((idx, slot)) = self.slots.next_back() {
+            if let Occupied(value) = slot.get() {
+                let kd = KeyData::new(idx as u32, slot.version);
+                self.num_left -= 1;
+                return Some((kd.into(), value));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, &'a mut V)> {
+        // All remaining slots are vacant, no need to scan them.
+        if self.num_left == 0 {
+            return None;
+        }
+
+        while let Some((idx, slot)) = self.slots.next_back() {
+            let version = slot.version;
+            if let OccupiedMut(value) = slot.get_mut() {
+                let kd = KeyData::new(idx as u32, version);
+                self.num_left -= 1;
+                return Some((kd.into(), value));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<K> {
+        self.inner.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for Values<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
 impl<'a, K: Key, V> IntoIterator for &'a SlotMap<K, V> {
     type Item = (K, &'a V);
     type IntoIter = Iter<'a, K, V>;
@@ -1188,9 +2914,12 @@ impl<K: Key, V> IntoIterator for SlotMap<K, V> {
     type Item = (K, V);
     type IntoIter = IntoIter<K, V>;
 
-    fn into_iter(self) -> Self::IntoIter {
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.lower_max_occupied();
         let len = self.len();
-        let mut it = self.slots.into_iter().enumerate();
+        let mut slots = self.slots;
+        slots.truncate(self.max_occupied as usize + 1);
+        let mut it = slots.into_iter().enumerate();
         it.next(); // Skip sentinel.
         IntoIter {
             num_left: len,
@@ -1206,7 +2935,10 @@ impl<'a, K: Key, V> FusedIterator for Keys<'a, K, V> {}
 impl<'a, K: Key, V> FusedIterator for Values<'a, K, V> {}
 impl<'a, K: Key, V> FusedIterator for ValuesMut<'a, K, V> {}
 impl<'a, K: Key, V> FusedIterator for Drain<'a, K, V> {}
+impl<'a, K: Key, V, F: FnMut(K, &mut V) -> bool> FusedIterator for DrainFilter<'a, K, V, F> {}
 impl<K: Key, V> FusedIterator for IntoIter<K, V> {}
+impl<K: Key, V> FusedIterator for IntoKeys<K, V> {}
+impl<K: Key, V> FusedIterator for IntoValues<K, V> {}
 
 impl<'a, K: Key, V> ExactSizeIterator for Iter<'a, K, V> {}
 impl<'a, K: Key, V> ExactSizeIterator for IterMut<'a, K, V> {}
@@ -1215,6 +2947,8 @@ impl<'a, K: Key, V> ExactSizeIterator for Values<'a, K, V> {}
 impl<'a, K: Key, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
 impl<'a, K: Key, V> ExactSizeIterator for Drain<'a, K, V> {}
 impl<K: Key, V> ExactSizeIterator for IntoIter<K, V> {}
+impl<K: Key, V> ExactSizeIterator for IntoKeys<K, V> {}
+impl<K: Key, V> ExactSizeIterator for IntoValues<K, V> {}
 
 // Serialization with serde.
 #[cfg(feature = "serde")]
@@ -1229,22 +2963,6 @@ mod serialize {
         version: u32,
     }
 
-    impl<T: Serialize> Serialize for Slot<T> {
-        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-        {
-            let serde_slot = SerdeSlot {
-                version: self.version,
-                value: match self.get() {
-                    Occupied(value) => Some(value),
-                    Vacant(_) => None,
-                },
-            };
-            serde_slot.serialize(serializer)
-        }
-    }
-
     impl<'de, T> Deserialize<'de> for Slot<T>
     where
         T: Deserialize<'de>,
@@ -1276,7 +2994,14 @@ mod serialize {
         where
             S: Serializer,
         {
-            self.slots.serialize(serializer)
+            let slots = self.slots.iter().enumerate().map(|(i, slot)| SerdeSlot {
+                value: match slot.get() {
+                    Occupied(value) => Some(value),
+                    Vacant(_) => None,
+                },
+                version: slot.exported_version(i),
+            });
+            serializer.collect_seq(slots)
         }
     }
 
@@ -1299,27 +3024,26 @@ mod serialize {
                 return Err(de::Error::custom(&"first slot not empty"));
             }
 
-            slots[0].version = 0;
+            if slots.iter().skip(1).any(|slot| slot.version == 0) {
+                return Err(de::Error::custom(&"version 0 outside the sentinel"));
+            }
+
+            // The sentinel's version is kept, it guards slots released by
+            // shrink_to_fit.
             slots[0].u.next_free = 0;
 
             // We have our slots, rebuild freelist.
-            let mut num_elems = 0;
-            let mut next_free = slots.len();
-            for (i, slot) in slots[1..].iter_mut().enumerate() {
-                if slot.occupied() {
-                    num_elems += 1;
-                } else {
-                    slot.u.next_free = next_free as u32;
-                    next_free = i + 1;
-                }
-            }
-
-            Ok(Self {
-                num_elems,
+            let mut sm = Self {
                 slots,
-                free_head: next_free as u32,
+                free_head: 0,
+                free_tail: 0,
+                num_elems: 0,
+                max_occupied: 0,
+                policy: ReusePolicy::Lifo,
                 _k: PhantomData,
-            })
+            };
+            sm.rebuild_freelist();
+            Ok(sm)
         }
     }
 }
@@ -1402,23 +3126,234 @@ mod tests {
                     assert!(i == j);
                 }
             }
-        }
+        }
+
+        for i in 0..keys.len() {
+            for j in 0..keys.len() {
+                for k in 0..keys.len() {
+                    if let Some([r0, r1, r2]) = sm.get_disjoint_mut([keys[i], keys[j], keys[k]]) {
+                        *r0 ^= *r1;
+                        *r0 = r0.wrapping_add(*r2);
+                        *r1 ^= *r0;
+                        *r1 = r1.wrapping_add(*r2);
+                        *r2 ^= *r0;
+                        *r2 = r2.wrapping_add(*r1);
+                    } else {
+                        assert!(i == j || j == k || i == k);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(has_min_const_generics)]
+    #[test]
+    fn disjoint_edge_cases() {
+        // Intended to be run with miri to find any potential UB.
+        let mut sm = SlotMap::new();
+        let a = sm.insert(0);
+        let b = sm.insert(1);
+        let c = sm.insert(2);
+
+        let empty: [&mut i32; 0] = [];
+        assert_eq!(sm.get_disjoint_mut([]), Some(empty));
+        let [ra] = sm.get_disjoint_mut([a]).unwrap();
+        *ra += 10;
+        assert_eq!(sm[a], 10);
+
+        // Duplicates anywhere in the array are rejected.
+        assert_eq!(sm.get_disjoint_mut([a, b, a]), None);
+        assert_eq!(sm.get_disjoint_mut([a, b, c, c]), None);
+
+        // Stale keys are rejected, also when their slot has been reused.
+        sm.remove(c);
+        assert_eq!(sm.get_disjoint_mut([c]), None);
+        assert_eq!(sm.get_disjoint_mut([a, b, c]), None);
+        let c2 = sm.insert(3);
+        assert_eq!(sm.get_disjoint_mut([c, c2]), None);
+        assert_eq!(sm.get_disjoint_mut([c2, c]), None);
+
+        // A failed call must leave the map untouched.
+        assert_eq!(sm.len(), 3);
+        assert!(sm.contains_key(a) && sm.contains_key(b) && sm.contains_key(c2));
+
+        let [rc, rb, ra] = sm.get_disjoint_mut([c2, b, a]).unwrap();
+        core::mem::swap(rc, ra);
+        *rb += 100;
+        assert_eq!(sm[a], 3);
+        assert_eq!(sm[b], 101);
+        assert_eq!(sm[c2], 10);
+    }
+
+    #[test]
+    fn clone_preserves_keys() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        for k in keys.iter().step_by(3) {
+            sm.remove(*k);
+        }
+
+        let mut clone = sm.clone();
+        for (k, v) in &sm {
+            assert_eq!(clone.get(k), Some(v));
+        }
+
+        // The free list is preserved, so both maps hand out the same keys.
+        for i in 0..100 {
+            assert_eq!(sm.insert(i), clone.insert(i));
+        }
+        assert_eq!(clone.len(), sm.len());
+    }
+
+    #[test]
+    fn clone_from_reuses_allocation() {
+        let mut src = SlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| src.insert(i.to_string())).collect();
+        for k in keys.iter().step_by(7) {
+            src.remove(*k);
+        }
+
+        let mut dst = SlotMap::with_capacity(1000);
+        dst.insert(String::from("scratch"));
+        let ptr = dst.slots.as_ptr();
+        dst.clone_from(&src);
+        assert_eq!(dst.slots.as_ptr(), ptr);
+        assert_eq!(dst.check_invariants(), Ok(()));
+        assert!(dst.iter().eq(src.iter()));
+
+        let mut fresh = src.clone();
+        for i in 0..20 {
+            let v = i.to_string();
+            assert_eq!(dst.insert(v.clone()), fresh.insert(v));
+        }
+    }
+
+    #[test]
+    fn try_insert_with_key_failure() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(0);
+        sm.remove(k);
+
+        // Failing repeatedly hands out the same key and burns no versions.
+        let mut offered = Vec::new();
+        for _ in 0..3 {
+            let r = sm.try_insert_with_key(|k| {
+                offered.push(k);
+                Err(())
+            });
+            assert_eq!(r, Err(()));
+        }
+        assert!(offered.windows(2).all(|w| w[0] == w[1]));
+        assert_eq!(sm.insert(1), offered[0]);
+
+        // A panicking closure leaves the map usable.
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sm.insert_with_key(|_| panic!("oops"));
+        }));
+        assert!(r.is_err());
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.len(), 1);
+        sm.insert(2);
+        assert_eq!(sm.len(), 2);
+    }
+
+    #[test]
+    fn debug_output() {
+        let mut sm = SlotMap::new();
+        let foo = sm.insert("foo");
+        let tmp = sm.insert("tmp");
+        let bar = sm.insert("bar");
+        sm.remove(tmp);
+
+        let expected = format!("{{{:?}: \"foo\", {:?}: \"bar\"}}", foo, bar);
+        assert_eq!(format!("{:?}", sm), expected);
+        assert_eq!(format!("{:?}", sm.keys()), format!("[{:?}, {:?}]", foo, bar));
+        assert_eq!(format!("{:?}", sm.values()), "[\"foo\", \"bar\"]");
+
+        let mut iter = sm.iter();
+        iter.next();
+        assert_eq!(format!("{:?}", iter), format!("[({:?}, \"bar\")]", bar));
+        assert_eq!(format!("{:?}", sm.iter_mut()), "IterMut { remaining: 2 }");
+
+        let mut drain = sm.drain();
+        drain.next();
+        assert_eq!(format!("{:?}", drain), format!("[({:?}, \"bar\")]", bar));
+    }
+
+    #[cfg(has_min_const_generics)]
+    #[test]
+    fn get_many() {
+        let mut sm = SlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+        let c = sm.insert(3);
+        sm.remove(c);
+
+        let empty: [&i32; 0] = [];
+        assert_eq!(sm.get_many([]), Some(empty));
+        assert_eq!(sm.get_many([a, a, b, a]), Some([&1, &1, &2, &1]));
+        assert_eq!(sm.get_many([a, c]), None);
+        assert_eq!(sm.get_many([KeyData::null().into(), a]), None);
+        let c2 = sm.insert(4);
+        assert_eq!(sm.get_many([c2, b, c]), None);
+        assert_eq!(sm.get_many([c2, b]), Some([&4, &2]));
+    }
+
+    #[test]
+    fn get2_mut() {
+        let mut sm = SlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+
+        // Aliasing, with the same key and with a stale key into the same slot.
+        assert_eq!(sm.get2_mut(a, a), None);
+        sm.remove(a);
+        let a2 = sm.insert(3);
+        assert_eq!(a.data().idx, a2.data().idx);
+        assert_eq!(sm.get2_mut(a2, a), None);
+        assert_eq!(sm.get2_mut(a, a2), None);
+
+        // Stale key into a different slot.
+        assert_eq!(sm.get2_mut(a, b), None);
+        assert_eq!(sm.get2_mut(b, a), None);
+
+        let (x, y) = sm.get2_mut(b, a2).unwrap();
+        core::mem::swap(x, y);
+        assert_eq!(sm[a2], 2);
+        assert_eq!(sm[b], 3);
+    }
+
+    quickcheck! {
+        fn qc_double_ended_equiv_vec(values: Vec<u32>, removals: Vec<usize>, ends: Vec<bool>) -> bool {
+            let mut sm = SlotMap::new();
+            let keys: Vec<_> = values.iter().map(|v| sm.insert(*v)).collect();
+            for r in removals {
+                if !keys.is_empty() {
+                    sm.remove(keys[r % keys.len()]);
+                }
+            }
 
-        for i in 0..keys.len() {
-            for j in 0..keys.len() {
-                for k in 0..keys.len() {
-                    if let Some([r0, r1, r2]) = sm.get_disjoint_mut([keys[i], keys[j], keys[k]]) {
-                        *r0 ^= *r1;
-                        *r0 = r0.wrapping_add(*r2);
-                        *r1 ^= *r0;
-                        *r1 = r1.wrapping_add(*r2);
-                        *r2 ^= *r0;
-                        *r2 = r2.wrapping_add(*r1);
-                    } else {
-                        assert!(i == j || j == k || i == k);
-                    }
+            // The model is whatever forward iteration yields.
+            let mut model: std::collections::VecDeque<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+            let mut iter = sm.iter();
+            // repeat_n needs a newer Rust than the minimum supported one.
+            #[allow(clippy::manual_repeat_n)]
+            let tail = core::iter::repeat(false).take(model.len() + 1);
+            for back in ends.into_iter().chain(tail) {
+                let (expected, actual) = if back {
+                    (model.pop_back(), iter.next_back())
+                } else {
+                    (model.pop_front(), iter.next())
+                };
+                if actual.map(|(k, v)| (k, *v)) != expected || iter.len() != model.len() {
+                    return false;
                 }
             }
+
+            let keys_rev: Vec<_> = sm.keys().rev().collect();
+            let mut keys_fwd: Vec<_> = sm.keys().collect();
+            keys_fwd.reverse();
+            keys_rev == keys_fwd
         }
     }
 
@@ -1485,6 +3420,10 @@ mod tests {
 
                     _ => unreachable!(),
                 }
+
+                if sm.check_invariants().is_err() {
+                    return false;
+                }
             }
 
             let mut smv: Vec<_> = sm.values().collect();
@@ -1495,6 +3434,881 @@ mod tests {
         }
     }
 
+    #[test]
+    fn from_iter() {
+        let sm: SlotMap<DefaultKey, i32> = core::iter::empty().collect();
+        assert!(sm.is_empty());
+
+        let sm: SlotMap<DefaultKey, usize> = (0..10_000).collect();
+        assert_eq!(sm.len(), 10_000);
+        assert!(sm.values().copied().eq(0..10_000));
+    }
+
+    #[test]
+    fn insert_from_iter_matches_insert() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for k in keys.iter().step_by(3) {
+            sm.remove(*k);
+        }
+
+        let mut expected = sm.clone();
+        let expected_keys: Vec<_> = (10..20).map(|i| expected.insert(i)).collect();
+        assert_eq!(sm.insert_from_iter(10..20), expected_keys);
+        assert!(sm.iter().eq(expected.iter()));
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // New slots start past the versions of released slots.
+        let k = sm.insert(20);
+        sm.remove(k);
+        sm.shrink_to_fit();
+        let keys = sm.insert_from_iter(21..23);
+        assert_eq!(keys[0].data().idx, k.data().idx);
+        assert!(keys[0] != k && sm.get(k).is_none());
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn insert_from_iter_panicking_iterator() {
+        let mut sm = SlotMap::new();
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sm.insert_from_iter((0..10).map(|i| if i == 5 { panic!() } else { i }));
+        }));
+        assert!(r.is_err());
+
+        // The values inserted before the panic stay.
+        assert_eq!(sm.len(), 5);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k = sm.insert(5);
+        assert_eq!(sm[k], 5);
+        assert!(sm.values().copied().eq(0..6));
+    }
+
+    #[test]
+    fn eq_ignores_history() {
+        let mut a = SlotMap::new();
+        let mut b = SlotMap::new();
+        let ka: Vec<_> = (0..4).map(|i| a.insert(i)).collect();
+        let kb: Vec<_> = (0..4).map(|i| b.insert(i)).collect();
+        assert_eq!(a, b);
+
+        // Same live content, but the free lists end up in a different order.
+        a.remove(ka[1]);
+        a.remove(ka[2]);
+        b.remove(kb[2]);
+        b.remove(kb[1]);
+        assert_eq!(a, b);
+
+        // Equal values under different keys are not equal.
+        let c: SlotMap<_, _> = vec![0, 3].into_iter().collect();
+        assert_ne!(a, c);
+
+        a[ka[3]] = 5;
+        assert_ne!(a, b);
+        a[ka[3]] = 3;
+        a.insert(7);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn swap() {
+        let mut sm = SlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+
+        assert!(sm.swap(a, a));
+        assert_eq!(sm[a], 1);
+        assert!(sm.swap(a, b));
+        assert_eq!((sm[a], sm[b]), (2, 1));
+
+        // Stale keys, both in the same slot as a valid key and in a vacant one.
+        sm.remove(a);
+        let a2 = sm.insert(3);
+        assert!(!sm.swap(a, a));
+        assert!(!sm.swap(a, a2));
+        assert!(!sm.swap(b, a));
+        assert_eq!((sm[a2], sm[b]), (3, 1));
+    }
+
+    #[test]
+    fn drain_filter() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+
+        {
+            let mut evens = sm.drain_filter(|_, v| *v % 2 == 0);
+            assert_eq!(evens.next(), Some((keys[0], 0)));
+            assert_eq!(evens.next(), Some((keys[2], 2)));
+        }
+
+        // Unvisited matches stay when the iterator is dropped early.
+        assert_eq!(sm.len(), 8);
+        assert!(!sm.contains_key(keys[0]) && !sm.contains_key(keys[2]));
+        assert!(sm.contains_key(keys[4]));
+
+        let rest: Vec<_> = sm.drain_filter(|_, v| *v % 2 == 0).map(|(_, v)| v).collect();
+        assert_eq!(rest, vec![4, 6, 8]);
+        assert!(sm.values().all(|v| v % 2 == 1));
+
+        // The freed slots are reused, so the free list is intact.
+        let new_keys: Vec<_> = (0..5).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.len(), 10);
+        assert_eq!(sm.slots.len(), 11);
+        for k in new_keys {
+            assert!(keys.iter().all(|old| *old != k));
+        }
+    }
+
+    #[test]
+    fn try_insert_full() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(0);
+        sm.remove(k);
+
+        // Pretend the map is one element away from full. There is a vacant slot
+        // so this does not need to allocate.
+        sm.num_elems = u32::MAX - 2;
+        assert!(!sm.is_full());
+        let k = sm.try_insert(1).unwrap();
+        assert_eq!(sm[k], 1);
+
+        assert!(sm.is_full());
+        assert_eq!(sm.try_insert(2), Err(2));
+        assert_eq!(sm.len(), u32::MAX as usize - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "number of elements overflow")]
+    fn insert_full() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(0);
+        sm.remove(k);
+        sm.num_elems = u32::MAX - 1;
+        sm.insert(1);
+    }
+
+    #[test]
+    fn foreign_keys_out_of_bounds() {
+        let mut big = SlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| big.insert(i)).collect();
+
+        let mut sm = SlotMap::new();
+        sm.insert(0);
+        let large = [
+            KeyData::from_ffi(u64::from(u32::MAX - 1) | (1 << 32)),
+            KeyData::from_ffi(u64::MAX),
+            KeyData::null(),
+        ];
+        for key in keys.into_iter().chain(large.iter().map(|&kd| kd.into())) {
+            if key.data().idx < 2 {
+                continue;
+            }
+            assert!(!sm.contains_key(key));
+            assert_eq!(sm.get(key), None);
+            assert_eq!(sm.get_mut(key), None);
+            assert_eq!(sm.get2_mut(key, key), None);
+            assert!(!sm.swap(key, key));
+            assert_eq!(sm.remove(key), None);
+        }
+        assert_eq!(sm.len(), 1);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut sm = SlotMap::new();
+        let mut old_keys = Vec::new();
+
+        // Churn the tail slots so they have differing versions.
+        for round in 0..5 {
+            let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+            for k in keys.iter().skip(round) {
+                sm.remove(*k);
+            }
+            old_keys.extend(keys.into_iter().skip(round));
+        }
+        let live: Vec<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+        let hole = live[1].0;
+        sm.remove(hole);
+        old_keys.push(hole);
+
+        sm.shrink_to_fit();
+        assert_eq!(sm.slots.len(), live.last().unwrap().0.data().idx as usize + 1);
+        assert!(old_keys.iter().all(|k| !sm.contains_key(*k)));
+        assert_eq!(sm.len(), live.len() - 1);
+
+        // Freshly created and reused slots never resurrect old keys.
+        let new_keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        assert!(old_keys.iter().all(|k| !sm.contains_key(*k)));
+        assert!(new_keys.iter().all(|k| !old_keys.contains(k)));
+
+        // Shrinking an empty map leaves only the sentinel.
+        let guard = sm.slots[0].version;
+        sm.clear();
+        sm.shrink_to_fit();
+        assert_eq!(sm.slots.len(), 1);
+        assert!(sm.slots[0].version >= guard);
+        assert!(new_keys.iter().all(|k| sm.insert(0) != *k));
+    }
+
+    #[test]
+    fn early_exit_after_last_element() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..1000).map(|i| sm.insert(i)).collect();
+        for k in &keys[2..] {
+            sm.remove(*k);
+        }
+
+        let mut iter = sm.iter();
+        assert_eq!(iter.next(), Some((keys[0], &0)));
+        assert_eq!(iter.next(), Some((keys[1], &1)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(sm.iter().rev().count(), 2);
+
+        // retain stops visiting once it has seen every element, but elements it
+        // removes are gone.
+        sm.retain(|_, v| *v == 1);
+        assert_eq!(sm.len(), 1);
+        sm.retain(|_, _| false);
+        assert!(sm.is_empty());
+
+        let k = sm.insert(5);
+        let mut drain = sm.drain();
+        assert_eq!(drain.next(), Some((k, 5)));
+        assert_eq!(drain.next(), None);
+    }
+
+    #[test]
+    fn key_of() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..10u64).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[4]);
+        for (k, v) in &sm {
+            assert_eq!(sm.key_of(v), Some(k));
+        }
+        assert_eq!(sm.key_of(&sm[keys[0]]), Some(keys[0]));
+        assert_eq!(sm.key_of(&sm[keys[9]]), Some(keys[9]));
+
+        let mut other = SlotMap::new();
+        let ko = other.insert(0u64);
+        assert_eq!(sm.key_of(&other[ko]), None);
+        assert_eq!(sm.key_of(&0), None);
+
+        // Zero-sized values still have a unique address per slot.
+        let mut zst = SlotMap::new();
+        let a = zst.insert(());
+        let b = zst.insert(());
+        assert_eq!(zst.key_of(&zst[b]), Some(b));
+        assert_eq!(zst.key_of(&zst[a]), Some(a));
+    }
+
+    #[test]
+    fn remove_many_duplicates() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        let mut batch: Vec<_> = keys.iter().copied().step_by(2).collect();
+        batch.extend_from_slice(&batch.clone());
+        assert_eq!(sm.remove_many(&batch), 5);
+        assert_eq!(sm.remove_many(&batch), 0);
+        assert_eq!(sm.len(), 5);
+
+        // The free list holds every freed slot exactly once.
+        let new_keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.len(), 15);
+        assert_eq!(sm.slots.len(), 16);
+        let unique: HashSet<_> = new_keys.iter().map(|k| k.data().idx).collect();
+        assert_eq!(unique.len(), 10);
+    }
+
+    #[test]
+    fn remove_many_panicking_drop() {
+        struct PanicOnDrop(bool);
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                if self.0 {
+                    panic!("oops");
+                }
+            }
+        }
+
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..6).map(|i| sm.insert(PanicOnDrop(i == 3))).collect();
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sm.remove_many(&keys[1..5]);
+        }));
+        assert!(r.is_err());
+
+        // The slots vacated before and during the panic are free again.
+        assert_eq!(sm.len(), 3);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        for _ in 0..3 {
+            sm.insert(PanicOnDrop(false));
+        }
+        assert_eq!(sm.slots.len(), 7);
+    }
+
+    #[test]
+    fn remove_many_reuse_order() {
+        for &policy in &[ReusePolicy::Lifo, ReusePolicy::Fifo] {
+            let mut one_by_one = SlotMap::new();
+            one_by_one.set_reuse_policy(policy);
+            let keys: Vec<_> = (0..10).map(|i| one_by_one.insert(i)).collect();
+            one_by_one.remove(keys[5]);
+            let mut batched = one_by_one.clone();
+
+            let batch = [keys[7], keys[2], keys[5], keys[9], keys[2]];
+            for &k in &batch {
+                one_by_one.remove(k);
+            }
+            assert_eq!(batched.remove_many(&batch), 3);
+            assert_eq!(batched.check_invariants(), Ok(()));
+
+            let reused: Vec<_> = (0..5).map(|i| one_by_one.insert(i)).collect();
+            let batch_reused: Vec<_> = (0..5).map(|i| batched.insert(i)).collect();
+            assert_eq!(reused, batch_reused);
+        }
+    }
+
+    #[test]
+    fn check_invariants_detects_corruption() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..5).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[1]);
+        sm.remove(keys[3]);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        let mut bad = sm.clone();
+        bad.num_elems += 1;
+        assert_eq!(
+            bad.check_invariants(),
+            Err(InvariantError::LenMismatch { len: 4, occupied: 3 })
+        );
+
+        let mut bad = sm.clone();
+        bad.free_head = 1;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::FreeListOccupied { idx: 1 }));
+
+        let mut bad = sm.clone();
+        bad.slots[2].u.next_free = 4;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::FreeListCycle));
+
+        let mut bad = sm.clone();
+        bad.slots[4].u.next_free = 9;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::FreeListOutOfBounds { idx: 9 }));
+
+        let mut bad = sm.clone();
+        bad.free_head = 2;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::UnreachableVacant { idx: 4 }));
+
+        let mut bad = sm.clone();
+        bad.free_tail = 4;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::FreeListTail { idx: 4 }));
+
+        let mut bad = sm.clone();
+        bad.max_occupied = 3;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::MaxOccupied { idx: 3 }));
+
+        let mut bad = sm.clone();
+        bad.max_occupied = 6;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::MaxOccupied { idx: 6 }));
+
+        let mut bad = sm.clone();
+        bad.slots[2].version = 0;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::ZeroVersion { idx: 2 }));
+    }
+
+    #[test]
+    fn version_wraps_past_sentinel_version() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(1);
+        sm.slots[1].version = u32::MAX;
+        sm.remove(KeyData::new(1, u32::MAX).into());
+        assert_eq!(sm.slots[1].version, 2);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k2 = sm.insert(2);
+        assert_ne!(k, k2);
+        assert_eq!(k2.data().version.get(), 3);
+
+        // A fresh reservation doesn't leave version 0 behind either.
+        let mut sm: SlotMap<DefaultKey, i32> = SlotMap::new();
+        let r = sm.reserve_key();
+        let rk = r.key();
+        sm.cancel_reserved(r);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.insert(1), rk);
+    }
+
+    #[test]
+    fn iter_stops_at_max_occupied() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        for &k in &keys[2..] {
+            sm.remove(k);
+        }
+
+        // Removing doesn't lower the bound, iterating mutably does.
+        assert_eq!(sm.max_occupied, 100);
+        assert_eq!(sm.values().rev().collect::<Vec<_>>(), vec![&1, &0]);
+        assert_eq!(sm.iter_mut().slots.len(), 2);
+        assert_eq!(sm.max_occupied, 2);
+        assert_eq!(sm.iter().slots.len(), 2);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // The slot vacated last is reused first, raising the bound again.
+        let k = sm.insert(2);
+        assert_eq!(sm.max_occupied, 100);
+        assert_eq!(sm.values().next_back(), Some(&2));
+        sm.remove(k);
+        assert_eq!(sm.max_occupied, 100);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // retain knows the highest element it kept.
+        sm.retain(|_, &mut v| v == 0);
+        assert_eq!(sm.max_occupied, 1);
+        assert_eq!(sm.values().rev().collect::<Vec<_>>(), vec![&0]);
+
+        sm.clear();
+        assert_eq!(sm.max_occupied, 0);
+        assert_eq!(sm.iter().next_back(), None);
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn reuse_policy() {
+        let mut sm = SlotMap::new();
+        sm.set_reuse_policy(ReusePolicy::Fifo);
+        let keys: Vec<_> = (0..6).map(|i| sm.insert(i)).collect();
+        for &i in &[4, 1, 3] {
+            sm.remove(keys[i]);
+        }
+
+        // Slots come back in the order they were vacated, never the slot
+        // vacated last while others are waiting.
+        let idx = |sm: &mut SlotMap<DefaultKey, i32>| sm.insert(0).data().idx;
+        assert_eq!(idx(&mut sm), 5);
+        let k = sm.insert(0);
+        sm.remove(k);
+        assert_eq!(idx(&mut sm), 4);
+        assert_eq!(idx(&mut sm), 2);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // Switching policies keeps the freelist intact.
+        let keys: Vec<_> = sm.keys().collect();
+        sm.remove(keys[0]);
+        sm.remove(keys[1]);
+        sm.set_reuse_policy(ReusePolicy::Lifo);
+        sm.remove(keys[2]);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(idx(&mut sm), keys[2].data().idx);
+        assert_eq!(idx(&mut sm), keys[0].data().idx);
+        assert_eq!(idx(&mut sm), keys[1].data().idx);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // The tail survives a rebuild of the freelist.
+        let mut sm = SlotMap::new();
+        sm.set_reuse_policy(ReusePolicy::Fifo);
+        let keys: Vec<_> = (0..6).map(|i| sm.insert(i)).collect();
+        for k in &keys[1..] {
+            sm.remove(*k);
+        }
+        sm.insert(0);
+        sm.shrink_to_fit();
+        assert_eq!(sm.check_invariants(), Ok(()));
+        sm.remove(keys[0]);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.clone().reuse_policy(), ReusePolicy::Fifo);
+    }
+
+    #[test]
+    fn into_keys_values() {
+        let drops = std::cell::RefCell::new(0usize);
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..10).map(|_| sm.insert(CountDrop(&drops))).collect();
+        sm.remove(keys[3]);
+        assert_eq!(*drops.borrow(), 1);
+
+        let mut values = sm.clone().into_values();
+        assert_eq!(values.len(), 9);
+        values.next();
+        assert_eq!(*drops.borrow(), 2);
+        assert_eq!(values.len(), 8);
+        drop(values);
+        assert_eq!(*drops.borrow(), 10);
+
+        let mut into_keys = sm.into_keys();
+        assert_eq!(into_keys.size_hint(), (9, Some(9)));
+        assert_eq!(into_keys.next(), Some(keys[0]));
+        assert_eq!(*drops.borrow(), 11);
+        let rest: Vec<_> = into_keys.collect();
+        assert_eq!(rest.len(), 8);
+        assert!(!rest.contains(&keys[3]));
+        assert_eq!(*drops.borrow(), 19);
+    }
+
+    #[test]
+    fn stats() {
+        let mut sm = SlotMap::new();
+        assert_eq!(sm.stats().max_index, None);
+        let keys: Vec<_> = (0..5).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[4]);
+        sm.remove(keys[1]);
+        let stats = sm.stats();
+        assert_eq!((stats.slots, stats.occupied, stats.vacant), (5, 3, 2));
+        assert_eq!(stats.max_index, Some(5));
+
+        sm.shrink_to_fit();
+        let stats = sm.stats();
+        assert_eq!((stats.slots, stats.occupied, stats.vacant), (4, 3, 1));
+        assert_eq!(stats.max_index, Some(4));
+
+        // Reserved slots are vacant, but not free, and count as handed out.
+        let mut sm: SlotMap<DefaultKey, i32> = SlotMap::new();
+        let r = sm.reserve_key();
+        let stats = sm.stats();
+        assert_eq!((stats.slots, stats.vacant, stats.free), (1, 1, 0));
+        assert_eq!(stats.max_index, Some(1));
+        sm.cancel_reserved(r);
+        let stats = sm.stats();
+        assert_eq!((stats.slots, stats.vacant, stats.free), (1, 1, 1));
+    }
+
+    #[test]
+    fn capacity_never_shrinks_on_remove() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        let cap = sm.capacity();
+        assert!(cap >= 100);
+
+        for &k in keys.iter().step_by(7) {
+            sm.remove(k);
+            assert_eq!(sm.capacity(), cap);
+        }
+        sm.remove_if(keys[1], |_| true);
+        sm.remove_many(&keys[10..20]);
+        assert_eq!(sm.capacity(), cap);
+        sm.retain(|_, v| *v % 2 == 0);
+        assert_eq!(sm.capacity(), cap);
+        sm.drain().take(5).for_each(drop);
+        assert_eq!(sm.capacity(), cap);
+        sm.clear();
+        assert_eq!(sm.capacity(), cap);
+
+        // All slots are free now, reserving them needs no new storage.
+        sm.reserve(100);
+        assert_eq!(sm.capacity(), cap);
+        for i in 0..100 {
+            sm.insert(i);
+        }
+        assert_eq!(sm.capacity(), cap);
+    }
+
+    #[test]
+    fn map_values() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for k in keys.iter().step_by(3) {
+            sm.remove(*k);
+        }
+
+        let mapped = sm.map_values(|k, v| (k, v * 2));
+        assert_eq!(mapped.len(), sm.len());
+        assert_eq!(mapped.check_invariants(), Ok(()));
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(mapped.get(*k).map(|&(_, v)| v), sm.get(*k).map(|v| v * 2));
+            if sm.contains_key(*k) {
+                assert_eq!(mapped[*k].0, *k);
+            } else {
+                assert_eq!(i % 3, 0);
+            }
+        }
+        assert!(mapped.keys().eq(sm.keys()));
+
+        let mut mapped = mapped;
+        for _ in 0..5 {
+            assert_eq!(mapped.insert((keys[0], 0)), sm.insert(0));
+        }
+    }
+
+    #[test]
+    fn map_values_reserved() {
+        let mut sm = SlotMap::new();
+        let a = sm.insert(1);
+        let r = sm.reserve_key();
+        let rk = r.key();
+        sm.insert(2);
+
+        // The reserved slot becomes a free slot that never hands out rk.
+        let mut mapped = sm.map_values(|_, v| v * 10);
+        assert_eq!(mapped.check_invariants(), Ok(()));
+        assert_eq!(mapped.stats().free, 1);
+        let k = mapped.insert(30);
+        assert_eq!(k.data().idx, rk.data().idx);
+        assert_ne!(k, rk);
+        assert!(!mapped.contains_key(rk));
+        assert_eq!(mapped[a], 10);
+
+        // The reservation is still good in the original.
+        assert_eq!(sm.insert_reserved(r, 3), rk);
+    }
+
+    #[test]
+    fn try_get() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(1);
+        assert_eq!(sm.try_get(k), Ok(&1));
+        *sm.try_get_mut(k).unwrap() = 2;
+        assert_eq!(sm.try_get(k), Ok(&2));
+
+        sm.remove(k);
+        assert_eq!(sm.try_get(k), Err(KeyError::Stale { current_version: 2 }));
+        let k2 = sm.insert(3);
+        assert_eq!(sm.try_get_mut(k), Err(KeyError::Stale { current_version: 3 }));
+        assert_eq!(sm.try_get(k2), Ok(&3));
+
+        assert_eq!(sm.try_get(DefaultKey::null()), Err(KeyError::Null));
+        let foreign = KeyData::new(100, 1).into();
+        assert_eq!(sm.try_get(foreign), Err(KeyError::OutOfBounds));
+        assert_eq!(sm.try_get_mut(foreign), Err(KeyError::OutOfBounds));
+    }
+
+    #[test]
+    fn compact() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        let mut live = std::collections::HashMap::new();
+        for (i, k) in keys.iter().enumerate() {
+            if i % 3 == 0 {
+                live.insert(*k, i);
+            } else {
+                sm.remove(*k);
+            }
+        }
+        let removed: Vec<_> = keys.iter().filter(|k| !live.contains_key(k)).collect();
+
+        let mut moved = 0;
+        sm.compact(|old, new, v| {
+            assert_eq!(live.remove(&old), Some(*v));
+            assert!(new.data().idx < old.data().idx);
+            live.insert(new, *v);
+            *v += 1000;
+            moved += 1;
+        });
+
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.slots.len(), 35);
+        assert_eq!(sm.len(), 34);
+        // Values in slots 1, 4, ..., 34 stay in place, the other 22 move.
+        assert_eq!(moved, 22);
+        for (k, i) in &live {
+            let expected = if keys.contains(k) { *i } else { *i + 1000 };
+            assert_eq!(sm[*k], expected);
+        }
+        for k in removed {
+            assert!(!sm.contains_key(*k));
+        }
+
+        // Keys of moved elements stay invalid when their slot comes back.
+        let new: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        for k in &keys {
+            if !live.contains_key(k) {
+                assert!(!sm.contains_key(*k));
+            }
+        }
+        for (i, k) in new.iter().enumerate() {
+            assert_eq!(sm[*k], i);
+        }
+
+        let mut empty: SlotMap<DefaultKey, i32> = SlotMap::new();
+        empty.compact(|_, _, _| panic!());
+        assert_eq!(empty.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn compact_reserved_last_slot() {
+        let mut sm = SlotMap::new();
+        let a = sm.insert("a");
+        let b = sm.insert("b");
+        let c = sm.insert("c");
+        let r = sm.reserve_key();
+        sm.remove(a);
+
+        let mut moved = Vec::new();
+        sm.compact(|old, new, _| moved.push((old, new)));
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].0, c);
+        let c = moved[0].1;
+
+        // The slot c moved out of is vacant now and reused, the reserved slot
+        // is not.
+        let x = sm.insert("x");
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(x.data().idx, 3);
+        assert_eq!(sm.len(), 3);
+        assert_eq!((sm[b], sm[c], sm[x]), ("b", "c", "x"));
+        let rk = r.key();
+        assert_eq!(sm.insert_reserved(r, "r"), rk);
+        assert_eq!(sm.len(), 4);
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn absorb() {
+        crate::new_key_type! {
+            struct ChunkKey;
+        }
+
+        let mut sm = SlotMap::new();
+        let a = sm.insert(String::from("a"));
+        let b = sm.insert(String::from("b"));
+        sm.remove(a);
+
+        let mut other: SlotMap<ChunkKey, String> = SlotMap::with_key();
+        let keys: Vec<_> = (0..10).map(|i| other.insert(i.to_string())).collect();
+        other.remove(keys[3]);
+
+        let mut remapped = Vec::new();
+        sm.absorb(other, |old, new| remapped.push((old, new)));
+        assert_eq!(remapped.len(), 9);
+        assert_eq!(sm.len(), 10);
+        assert_eq!(sm[b], "b");
+        for (old, new) in remapped {
+            let i = keys.iter().position(|k| *k == old).unwrap();
+            assert_eq!(sm[new], i.to_string());
+        }
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn reserve_key() {
+        let mut sm = SlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+        sm.remove(a);
+
+        // One reservation reuses the vacant slot, the other pushes a new one.
+        let r1 = sm.reserve_key();
+        let r2 = sm.reserve_key();
+        let (k1, k2) = (r1.key(), r2.key());
+        assert_eq!(k1.data().idx, a.data().idx);
+        assert_ne!(k1, a);
+        assert_eq!(k2.data().idx, 3);
+
+        assert_eq!(sm.len(), 1);
+        assert!(!sm.contains_key(k1) && !sm.contains_key(k2));
+        assert_eq!(sm.get(k2), None);
+        assert_eq!(sm.remove(k1), None);
+        assert!(sm.keys().eq(vec![b]));
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // Neither shrinking nor compacting gives away reserved slots.
+        sm.shrink_to_fit();
+        sm.compact(|_, _, _| panic!());
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k3 = sm.insert(3);
+        assert!(k3 != k1 && k3 != k2);
+
+        sm.cancel_reserved(r1);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.insert(4), k1);
+        assert_eq!(sm.insert_reserved(r2, 5), k2);
+        assert_eq!(sm.len(), 4);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![4, 2, 5, 3]);
+    }
+
+    #[test]
+    fn cancel_reserved_fifo() {
+        let mut sm = SlotMap::new();
+        sm.set_reuse_policy(ReusePolicy::Fifo);
+        let keys: Vec<_> = (0..3).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[0]);
+        sm.remove(keys[1]);
+
+        // The cancelled slot goes to the back of the queue.
+        let reserved = sm.reserve_key();
+        let key = reserved.key();
+        assert_eq!(key.data().idx, keys[0].data().idx);
+        sm.cancel_reserved(reserved);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.insert(3).data().idx, keys[1].data().idx);
+        assert_eq!(sm.insert(4), key);
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "ReservedKey does not belong to this SlotMap")]
+    fn reserve_key_foreign() {
+        let mut other: SlotMap<DefaultKey, i32> = SlotMap::new();
+        let mut sm = SlotMap::new();
+        sm.insert(1);
+        sm.insert(2);
+        let r = other.reserve_key();
+        sm.insert_reserved(r, 3);
+    }
+
+    #[test]
+    fn get_by_index() {
+        let mut sm = SlotMap::new();
+        let keys: Vec<_> = (0..5).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[2]);
+        let reused = sm.insert(10);
+
+        assert_eq!(sm.get_by_index(0), None);
+        for (i, k) in keys.iter().enumerate() {
+            let idx = k.data().idx;
+            if i == 2 {
+                assert_eq!(sm.get_by_index(idx), Some((reused, &10)));
+            } else {
+                assert_eq!(sm.get_by_index(idx), Some((*k, &(i as i32))));
+            }
+        }
+        assert_eq!(sm.get_by_index(6), None);
+        assert_eq!(sm.get_by_index(u32::MAX), None);
+
+        sm.remove(keys[4]);
+        assert_eq!(sm.get_by_index_mut(keys[4].data().idx), None);
+        let (k, v) = sm.get_by_index_mut(keys[0].data().idx).unwrap();
+        assert_eq!(k, keys[0]);
+        *v = 7;
+        assert_eq!(sm[keys[0]], 7);
+    }
+
+    #[test]
+    fn index_mut() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(1);
+        sm[k] = 5;
+        sm[k] += 1;
+        assert_eq!(sm[k], 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid SlotMap key Key(1v1): key is stale, slot currently at version 3")]
+    fn index_stale_key() {
+        let mut sm = SlotMap::new();
+        let k = sm.insert(1);
+        sm.remove(k);
+        sm.insert(2);
+        sm[k] = 3;
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid SlotMap key Key(2v1): key is out of range, slot does not exist")]
+    fn index_out_of_range_key() {
+        let mut other = SlotMap::new();
+        other.insert(1);
+        let k = other.insert(2);
+        let sm: SlotMap<_, i32> = SlotMap::new();
+        let _ = sm[k];
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid SlotMap key Key(4294967295v1): key is null")]
+    fn index_null_key() {
+        let sm: SlotMap<DefaultKey, i32> = SlotMap::new();
+        let _ = sm[DefaultKey::null()];
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn slotmap_serde() {
@@ -1521,6 +4335,63 @@ mod tests {
         smkv.sort();
         dekv.sort();
         assert_eq!(smkv, dekv);
+        assert_eq!(de, sm);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_malicious() {
+        type Sm = SlotMap<DefaultKey, i32>;
+        let inconsistent = r#"[{"value":null,"version":0},{"value":5,"version":2}]"#;
+        assert!(serde_json::from_str::<Sm>(inconsistent).is_err());
+        let missing = r#"[{"value":null,"version":0},{"value":null,"version":3}]"#;
+        assert!(serde_json::from_str::<Sm>(missing).is_err());
+        let occupied_sentinel = r#"[{"value":1,"version":1}]"#;
+        assert!(serde_json::from_str::<Sm>(occupied_sentinel).is_err());
+        assert!(serde_json::from_str::<Sm>("[]").is_err());
+
+        let zero_version = r#"[{"value":null,"version":0},{"value":null,"version":0},{"value":7,"version":5}]"#;
+        assert!(serde_json::from_str::<Sm>(zero_version).is_err());
+
+        let ok = r#"[{"value":null,"version":0},{"value":null,"version":2},{"value":7,"version":5}]"#;
+        let mut sm: Sm = serde_json::from_str(ok).unwrap();
+        assert_eq!(sm.len(), 1);
+        let k = sm.insert(8);
+        assert_eq!(k.data().idx, 1);
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![8, 7]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_shrunk() {
+        let mut sm = SlotMap::new();
+        sm.insert(0);
+        let old = sm.insert(1);
+        sm.remove(old);
+        sm.shrink_to_fit();
+
+        let ser = serde_json::to_string(&sm).unwrap();
+        let mut de: SlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+        let new = de.insert(2);
+        assert_eq!(new.data().idx, old.data().idx);
+        assert_eq!(de.get(old), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_reserved() {
+        let mut sm = SlotMap::new();
+        sm.insert(0);
+        let reserved = sm.reserve_key();
+        let key = reserved.key();
+
+        let ser = serde_json::to_string(&sm).unwrap();
+        let mut de: SlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+        assert_eq!(de.check_invariants(), Ok(()));
+        let new = de.insert(1);
+        assert_eq!(new.data().idx, key.data().idx);
+        assert_ne!(new, key);
+        assert!(!de.contains_key(key));
     }
 
     #[cfg(feature = "serde")]
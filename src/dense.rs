@@ -9,7 +9,8 @@
 #[cfg(all(nightly, any(doc, feature = "unstable")))]
 use alloc::collections::TryReserveError;
 use alloc::vec::Vec;
-use core::iter::FusedIterator;
+use core::cmp::Ordering;
+use core::iter::{Extend, FromIterator, FusedIterator};
 #[allow(unused_imports)] // MaybeUninit is only used on nightly at the moment.
 use core::mem::MaybeUninit;
 use core::ops::{Index, IndexMut};
@@ -154,7 +155,7 @@ impl<K: Key, V> DenseSlotMap<K, V> {
     }
 
     /// Returns the number of elements the [`DenseSlotMap`] can hold without
-    /// reallocating.
+    /// reallocating any of its storage.
     ///
     /// # Examples
     ///
@@ -164,7 +165,9 @@ impl<K: Key, V> DenseSlotMap<K, V> {
     /// assert_eq!(sm.capacity(), 10);
     /// ```
     pub fn capacity(&self) -> usize {
-        self.keys.capacity()
+        // One slot is reserved for the sentinel.
+        let slots = self.slots.capacity() - 1;
+        self.keys.capacity().min(self.values.capacity()).min(slots)
     }
 
     /// Reserves capacity for at least `additional` more elements to be inserted
@@ -342,7 +345,8 @@ impl<K: Key, V> DenseSlotMap<K, V> {
     fn free_slot(&mut self, slot_idx: usize) -> u32 {
         let slot = &mut self.slots[slot_idx];
         let value_idx = slot.idx_or_free;
-        slot.version = slot.version.wrapping_add(1);
+        // Version 0 is reserved for the sentinel, skip it when wrapping around.
+        slot.version = slot.version.wrapping_add(1).max(2);
         slot.idx_or_free = self.free_head;
         self.free_head = slot_idx as u32;
         value_idx
@@ -369,6 +373,10 @@ impl<K: Key, V> DenseSlotMap<K, V> {
     /// Removes a key from the slot map, returning the value at the key if the
     /// key was not previously removed.
     ///
+    /// This takes constant time: the last value is moved into the place of the
+    /// removed one, which changes the iteration order. Use
+    /// [`remove_stable`](Self::remove_stable) to keep the order.
+    ///
     /// # Examples
     ///
     /// ```
@@ -387,6 +395,45 @@ impl<K: Key, V> DenseSlotMap<K, V> {
         }
     }
 
+    /// Removes a key from the slot map, returning the value at the key if the
+    /// key was not previously removed, while keeping the remaining values in
+    /// the same order.
+    ///
+    /// Unlike [`remove`](Self::remove), which takes constant time, this takes
+    /// time linear in the number of values after the removed one, as they
+    /// are all shifted down a position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let ka = sm.insert("a");
+    /// let kb = sm.insert("b");
+    /// let kc = sm.insert("c");
+    /// assert_eq!(sm.remove_stable(ka), Some("a"));
+    /// assert_eq!(sm.remove_stable(ka), None);
+    /// assert_eq!(sm.as_slice(), &["b", "c"]);
+    /// assert_eq!(sm[kc], "c");
+    /// ```
+    pub fn remove_stable(&mut self, key: K) -> Option<V> {
+        let kd = key.data();
+        if !self.contains_key(kd.into()) {
+            return None;
+        }
+
+        let value_idx = self.free_slot(kd.idx as usize) as usize;
+        let _ = self.keys.remove(value_idx);
+        let value = self.values.remove(value_idx);
+
+        // Everything after the removed value moved down one position.
+        for (i, k) in self.keys.iter().enumerate().skip(value_idx) {
+            self.slots[k.data().idx as usize].idx_or_free = i as u32;
+        }
+
+        Some(value)
+    }
+
     /// Retains only the elements specified by the predicate.
     ///
     /// In other words, remove all key-value pairs `(k, v)` such that
@@ -431,6 +478,81 @@ impl<K: Key, V> DenseSlotMap<K, V> {
         }
     }
 
+    /// Sorts the values with the comparator function `cmp`, changing the
+    /// order in which they are iterated over and laid out in
+    /// [`as_slice`](Self::as_slice). Keys are not affected, every key still
+    /// refers to the same value afterwards.
+    ///
+    /// This sort is stable, equal values keep their relative order. If `cmp`
+    /// panics the order of the values is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let ka = sm.insert("c");
+    /// let kb = sm.insert("a");
+    /// let kc = sm.insert("b");
+    /// sm.sort_by(|a, b| a.cmp(b));
+    /// assert_eq!(sm.as_slice(), &["a", "b", "c"]);
+    /// assert_eq!(sm[ka], "c");
+    /// assert_eq!(sm[kb], "a");
+    /// assert_eq!(sm[kc], "b");
+    /// ```
+    pub fn sort_by<F>(&mut self, mut cmp: F)
+    where
+        F: FnMut(&V, &V) -> Ordering,
+    {
+        // Sort a permutation rather than the values themselves, so a panic in
+        // cmp can't leave the keys and values out of sync.
+        let mut perm: Vec<usize> = (0..self.values.len()).collect();
+        perm.sort_by(|&a, &b| cmp(&self.values[a], &self.values[b]));
+
+        // Apply the permutation one cycle at a time, position i receives the
+        // element at perm[i].
+        for i in 0..perm.len() {
+            let mut cur = i;
+            while perm[cur] != i {
+                let next = perm[cur];
+                perm[cur] = cur;
+                self.keys.swap(cur, next);
+                self.values.swap(cur, next);
+                cur = next;
+            }
+            perm[cur] = cur;
+        }
+
+        for (i, k) in self.keys.iter().enumerate() {
+            self.slots[k.data().idx as usize].idx_or_free = i as u32;
+        }
+    }
+
+    /// Sorts the values with the key extraction function `f`, see
+    /// [`sort_by`](Self::sort_by).
+    ///
+    /// This sort is stable, equal values keep their relative order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let ka = sm.insert(-3i32);
+    /// let kb = sm.insert(1);
+    /// let kc = sm.insert(2);
+    /// sm.sort_by_key(|v| v.abs());
+    /// assert_eq!(sm.as_slice(), &[1, 2, -3]);
+    /// assert_eq!(sm[ka], -3);
+    /// ```
+    pub fn sort_by_key<B, F>(&mut self, mut f: F)
+    where
+        B: Ord,
+        F: FnMut(&V) -> B,
+    {
+        self.sort_by(|a, b| f(a).cmp(&f(b)))
+    }
+
     /// Clears the slot map. Keeps the allocated memory for reuse.
     ///
     /// # Examples
@@ -569,6 +691,54 @@ impl<K: Key, V> DenseSlotMap<K, V> {
         self.values.get_unchecked_mut(idx as usize)
     }
 
+    /// Returns mutable references to the values corresponding to the two given
+    /// keys. Both keys must be valid and refer to different values, otherwise
+    /// None is returned.
+    ///
+    /// Unlike [`get_disjoint_mut`](Self::get_disjoint_mut) this does not
+    /// require const generics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let ka = sm.insert(1);
+    /// let kb = sm.insert(2);
+    /// assert_eq!(sm.get2_mut(ka, ka), None); // Not disjoint.
+    /// if let Some((a, b)) = sm.get2_mut(ka, kb) {
+    ///     *a += 10;
+    ///     *b += 20;
+    /// }
+    /// assert_eq!(sm[ka], 11);
+    /// assert_eq!(sm[kb], 22);
+    /// ```
+    pub fn get2_mut(&mut self, a: K, b: K) -> Option<(&mut V, &mut V)> {
+        let value_idx = |key: K| {
+            let kd = key.data();
+            self.slots
+                .get(kd.idx as usize)
+                .filter(|slot| slot.version == kd.version.get())
+                .map(|slot| slot.idx_or_free as usize)
+        };
+        let ia = value_idx(a)?;
+        let ib = value_idx(b)?;
+
+        // Valid keys of different slots never share a value, but the check on
+        // the value indices is what makes handing out both references sound.
+        if ia == ib {
+            return None;
+        }
+
+        // Derive both references from a single raw pointer so that creating
+        // the second one does not invalidate the first. This is safe because
+        // we only store valid indices.
+        unsafe {
+            let values = self.values.as_mut_ptr();
+            Some((&mut *values.add(ia), &mut *values.add(ib)))
+        }
+    }
+
     /// Returns mutable references to the values corresponding to the given
     /// keys. All keys must be valid and disjoint, otherwise [`None`] is
     /// returned.
@@ -785,6 +955,144 @@ impl<K: Key, V> DenseSlotMap<K, V> {
             inner: self.iter_mut(),
         }
     }
+
+    /// Returns all values as a slice, in the same order as [`values`].
+    ///
+    /// The order of the values is unspecified, removing an element moves the
+    /// last value into its place. The order only changes when the slot map is
+    /// modified, so the position of a value stays the same as long as the
+    /// slot map is not.
+    ///
+    /// [`values`]: Self::values
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let k0 = sm.insert(10);
+    /// sm.insert(20);
+    /// sm.insert(30);
+    /// assert_eq!(sm.as_slice().iter().sum::<i32>(), 60);
+    ///
+    /// sm.remove(k0);
+    /// assert_eq!(sm.as_slice(), &[30, 20]);
+    /// ```
+    pub fn as_slice(&self) -> &[V] {
+        &self.values
+    }
+
+    /// Returns all values as a mutable slice, in the same order as
+    /// [`as_slice`](Self::as_slice).
+    ///
+    /// Values must only be modified in place through this slice. The keys
+    /// refer to positions in the slice, so moving values around, for example
+    /// by sorting or swapping, makes keys refer to the wrong values. This is
+    /// not unsafe, but almost certainly a bug. Use [`sort_by`](Self::sort_by)
+    /// to reorder the values without breaking the keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let k = sm.insert(1);
+    /// sm.insert(2);
+    /// sm.insert(3);
+    ///
+    /// let (left, right) = sm.values_mut_slice().split_at_mut(1);
+    /// left[0] += right.iter().sum::<i32>();
+    /// assert_eq!(sm[k], 6);
+    /// ```
+    pub fn values_mut_slice(&mut self) -> &mut [V] {
+        &mut self.values
+    }
+
+    /// Returns the keys of all elements as a slice, lined up with
+    /// [`as_slice`]: the key at position `i` is the key of the value at
+    /// position `i`.
+    ///
+    /// The correspondence is purely positional, any modification of the slot
+    /// map may move values and their keys to other positions.
+    ///
+    /// [`as_slice`]: Self::as_slice
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// sm.insert(10);
+    /// let k1 = sm.insert(-20);
+    /// sm.insert(30);
+    ///
+    /// let pos = sm.as_slice().iter().position(|&v| v < 0).unwrap();
+    /// assert_eq!(sm.keys_as_slice()[pos], k1);
+    /// ```
+    pub fn keys_as_slice(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// Returns the key of the value at position `idx` of
+    /// [`as_slice`](Self::as_slice), or [`None`] if `idx` is out of bounds.
+    ///
+    /// The correspondence is purely positional, any modification of the slot
+    /// map may move values and their keys to other positions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let k0 = sm.insert("foo");
+    /// let k1 = sm.insert("bar");
+    /// assert_eq!(sm.key_at_dense_index(1), Some(k1));
+    /// assert_eq!(sm.key_at_dense_index(2), None);
+    ///
+    /// sm.remove(k0);
+    /// assert_eq!(sm.key_at_dense_index(0), Some(k1));
+    /// ```
+    pub fn key_at_dense_index(&self, idx: usize) -> Option<K> {
+        self.keys.get(idx).copied()
+    }
+
+    /// Consumes the slot map, returning its values in the order of
+    /// [`as_slice`](Self::as_slice). The values are not copied, the storage
+    /// of the slot map is handed over as is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let k0 = sm.insert(1);
+    /// sm.insert(2);
+    /// sm.insert(3);
+    /// sm.remove(k0);
+    /// assert_eq!(sm.into_vec(), vec![3, 2]);
+    /// ```
+    pub fn into_vec(self) -> Vec<V> {
+        self.values
+    }
+
+    /// Consumes the slot map, returning its keys and values in the order of
+    /// [`keys_as_slice`](Self::keys_as_slice) and [`as_slice`](Self::as_slice).
+    /// Neither are copied, the storage of the slot map is handed over as is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = DenseSlotMap::new();
+    /// let k0 = sm.insert("foo");
+    /// let k1 = sm.insert("bar");
+    /// let (keys, values) = sm.into_vecs();
+    /// assert_eq!(keys, vec![k0, k1]);
+    /// assert_eq!(values, vec!["foo", "bar"]);
+    /// ```
+    pub fn into_vecs(self) -> (Vec<K>, Vec<V>) {
+        (self.keys, self.values)
+    }
 }
 
 impl<K: Key, V> Clone for DenseSlotMap<K, V>
@@ -808,12 +1116,55 @@ where
     }
 }
 
+/// Two slot maps are equal if they contain the same key-value pairs. Vacant
+/// slots and the order of the values are not taken into account.
+impl<K: Key, V: PartialEq> PartialEq for DenseSlotMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+
+        self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Key, V: Eq> Eq for DenseSlotMap<K, V> {}
+
 impl<K: Key, V> Default for DenseSlotMap<K, V> {
     fn default() -> Self {
         Self::with_key()
     }
 }
 
+impl<K: Key, V> FromIterator<V> for DenseSlotMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut sm = Self::with_capacity_and_key(iter.size_hint().0);
+        for value in iter {
+            sm.insert(value);
+        }
+        sm
+    }
+}
+
+/// Inserts every value, discarding the keys. Vacant slots are reused before
+/// the storage grows, just like with repeated [`insert`](DenseSlotMap::insert).
+impl<K: Key, V> Extend<V> for DenseSlotMap<K, V> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl<'a, K: Key, V: 'a + Copy> Extend<&'a V> for DenseSlotMap<K, V> {
+    fn extend<I: IntoIterator<Item = &'a V>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
 impl<K: Key, V> Index<K> for DenseSlotMap<K, V> {
     type Output = V;
 
@@ -1041,6 +1392,63 @@ impl<'a, K: 'a + Key, V> Iterator for ValuesMut<'a, K, V> {
     }
 }
 
+impl<K: Key, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        let key = self.inner_keys.next_back();
+        let value = self.inner_values.next_back();
+
+        if let (Some(k), Some(v)) = (key, value) {
+            Some((k, v))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, K: 'a + Key, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, &'a V)> {
+        let key = self.inner_keys.next_back();
+        let value = self.inner_values.next_back();
+
+        if let (Some(k), Some(v)) = (key, value) {
+            Some((*k, v))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, K: 'a + Key, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, &'a mut V)> {
+        let key = self.inner_keys.next_back();
+        let value = self.inner_values.next_back();
+
+        if let (Some(k), Some(v)) = (key, value) {
+            Some((*k, v))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, K: 'a + Key, V> DoubleEndedIterator for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<K> {
+        self.inner.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K: 'a + Key, V> DoubleEndedIterator for Values<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K: 'a + Key, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
 impl<'a, K: 'a + Key, V> IntoIterator for &'a DenseSlotMap<K, V> {
     type Item = (K, &'a V);
     type IntoIter = Iter<'a, K, V>;
@@ -1087,6 +1495,89 @@ impl<'a, K: 'a + Key, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
 impl<'a, K: 'a + Key, V> ExactSizeIterator for Drain<'a, K, V> {}
 impl<K: Key, V> ExactSizeIterator for IntoIter<K, V> {}
 
+// Parallel iteration with rayon.
+#[cfg(feature = "rayon")]
+mod parallel {
+    use rayon::iter::{
+        Copied, IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator,
+        ParallelIterator, Zip,
+    };
+    use rayon::slice::{Iter, IterMut};
+
+    use super::*;
+
+    #[cfg_attr(all(nightly, doc), doc(cfg(feature = "rayon")))]
+    impl<K: Key, V> DenseSlotMap<K, V> {
+        /// A parallel iterator over all key-value pairs, in the order of
+        /// [`as_slice`](Self::as_slice). The iterator element type is
+        /// `(K, &'a V)`.
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// # use slotmap::*;
+        /// use rayon::prelude::*;
+        ///
+        /// let mut sm = DenseSlotMap::new();
+        /// let k0 = sm.insert(10);
+        /// sm.insert(20);
+        /// sm.insert(30);
+        /// let sum: i32 = sm.par_iter().filter(|&(k, _)| k != k0).map(|(_, v)| v).sum();
+        /// assert_eq!(sum, 50);
+        /// ```
+        pub fn par_iter(&self) -> Zip<Copied<Iter<'_, K>>, Iter<'_, V>>
+        where
+            K: Send + Sync,
+            V: Sync,
+        {
+            self.keys.par_iter().copied().zip(self.values.par_iter())
+        }
+
+        /// A parallel iterator over all values, in the order of
+        /// [`as_slice`](Self::as_slice). The iterator element type is `&'a V`.
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// # use slotmap::*;
+        /// use rayon::prelude::*;
+        ///
+        /// let sm: DenseSlotMap<DefaultKey, u64> = (0..1000).collect();
+        /// assert_eq!(sm.par_values().sum::<u64>(), 499500);
+        /// ```
+        pub fn par_values(&self) -> Iter<'_, V>
+        where
+            V: Sync,
+        {
+            self.values.par_iter()
+        }
+
+        /// A parallel iterator over all values with mutable references, in the
+        /// order of [`as_slice`](Self::as_slice). The iterator element type is
+        /// `&'a mut V`.
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// # use slotmap::*;
+        /// use rayon::prelude::*;
+        ///
+        /// let mut sm = DenseSlotMap::new();
+        /// let k = sm.insert(1);
+        /// sm.insert(2);
+        /// sm.par_values_mut().for_each(|v| *v *= 10);
+        /// assert_eq!(sm[k], 10);
+        /// assert_eq!(sm.as_slice(), &[10, 20]);
+        /// ```
+        pub fn par_values_mut(&mut self) -> IterMut<'_, V>
+        where
+            V: Send,
+        {
+            self.values.par_iter_mut()
+        }
+    }
+}
+
 // Serialization with serde.
 #[cfg(feature = "serde")]
 mod serialize {
@@ -1147,6 +1638,10 @@ mod serialize {
 
             let mut next_free = serde_slots.len();
             for (i, serde_slot) in serde_slots.into_iter().enumerate().skip(1) {
+                if serde_slot.version == 0 {
+                    return Err(de::Error::custom(&"version 0 outside the sentinel"));
+                }
+
                 let occupied = serde_slot.version % 2 == 1;
                 if occupied ^ serde_slot.value.is_some() {
                     return Err(de::Error::custom(&"inconsistent occupation in Slot"));
@@ -1276,6 +1771,359 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get2_mut() {
+        let mut sm = DenseSlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+
+        // Aliasing, with the same key and with a stale key into the same slot.
+        assert_eq!(sm.get2_mut(a, a), None);
+        sm.remove(a);
+        let a2 = sm.insert(3);
+        assert_eq!(a.data().idx, a2.data().idx);
+        assert_eq!(sm.get2_mut(a2, a), None);
+        assert_eq!(sm.get2_mut(a, a2), None);
+
+        // Stale and null keys.
+        assert_eq!(sm.get2_mut(a, b), None);
+        assert_eq!(sm.get2_mut(b, a), None);
+        assert_eq!(sm.get2_mut(b, DefaultKey::null()), None);
+
+        // Values were moved around by the removal, keys still find them.
+        let (x, y) = sm.get2_mut(b, a2).unwrap();
+        assert_eq!((*x, *y), (2, 3));
+        core::mem::swap(x, y);
+        assert_eq!(sm[a2], 2);
+        assert_eq!(sm[b], 3);
+    }
+
+    #[test]
+    fn retain_swap_remove() {
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+
+        // Removing moves the last value into the current position, which must
+        // still be visited, and visited only once.
+        let mut visited = Vec::new();
+        sm.retain(|k, v| {
+            visited.push(k);
+            *v % 2 == 1
+        });
+        visited.sort();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(visited, sorted);
+
+        let mut survivors: Vec<_> = sm.values().copied().collect();
+        survivors.sort();
+        assert_eq!(survivors, (0..100).filter(|i| i % 2 == 1).collect::<Vec<_>>());
+        for (i, &k) in keys.iter().enumerate() {
+            assert_eq!(sm.get(k), if i % 2 == 1 { Some(&i) } else { None });
+        }
+    }
+
+    #[test]
+    fn drain_dropped_early() {
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+
+        let mut drained: Vec<_> = sm.drain().take(3).collect();
+        assert_eq!(drained.len(), 3);
+        assert!(sm.is_empty());
+        assert!(keys.iter().all(|&k| !sm.contains_key(k)));
+        for (k, v) in drained.drain(..) {
+            assert_eq!(keys[v], k);
+        }
+
+        // The slots are free for reuse, without reviving the drained keys.
+        let new: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.len(), 10);
+        assert!(new.iter().all(|k| !keys.contains(k)));
+    }
+
+    #[test]
+    fn from_iter() {
+        let sm: DenseSlotMap<DefaultKey, i32> = core::iter::empty().collect();
+        assert!(sm.is_empty());
+
+        let sm: DenseSlotMap<DefaultKey, i32> = (0..100).collect::<Vec<_>>().into_iter().collect();
+        assert_eq!(sm.len(), 100);
+        assert!(sm.keys.capacity() >= 100);
+        assert!(sm.values.capacity() >= 100);
+        assert!(sm.slots.capacity() >= 101);
+        assert!(sm.values().copied().eq(0..100));
+        for (i, k) in sm.keys().enumerate() {
+            assert_eq!(k.data(), KeyData::new(i as u32 + 1, 1));
+        }
+    }
+
+    #[test]
+    fn extend() {
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for &k in &keys[2..6] {
+            sm.remove(k);
+        }
+
+        // The vacant slots are filled before the storage grows.
+        sm.extend(vec![10, 11, 12, 13]);
+        assert_eq!(sm.len(), 10);
+        assert_eq!(sm.slots.len(), 11);
+
+        sm.extend(&[20, 21]);
+        assert_eq!(sm.len(), 12);
+        assert_eq!(sm.slots.len(), 13);
+        let mut values = sm.as_slice().to_vec();
+        values.sort();
+        assert_eq!(values, vec![0, 1, 6, 7, 8, 9, 10, 11, 12, 13, 20, 21]);
+    }
+
+    #[test]
+    fn clone_eq_scrambled() {
+        let mut a = DenseSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| a.insert(i)).collect();
+        for &k in &keys[..5] {
+            a.remove(k);
+        }
+
+        // Same content, different removal history and thus dense order.
+        let mut b = DenseSlotMap::new();
+        for i in 0..10 {
+            b.insert(i);
+        }
+        for &k in keys[..5].iter().rev() {
+            b.remove(k);
+        }
+        assert_ne!(a.as_slice(), b.as_slice());
+        assert_eq!(a, b);
+
+        // Clones keep the dense order and key validity.
+        let c = b.clone();
+        assert_eq!(c.as_slice(), b.as_slice());
+        assert_eq!(c.keys_as_slice(), b.keys_as_slice());
+        assert!(keys[..5].iter().all(|&k| !c.contains_key(k)));
+        assert_eq!(c, a);
+
+        let mut d = DenseSlotMap::new();
+        d.insert(-1);
+        d.clone_from(&a);
+        assert_eq!(d.iter().collect::<Vec<_>>(), a.iter().collect::<Vec<_>>());
+
+        d[keys[9]] = -9;
+        assert_ne!(d, a);
+        d.remove(keys[9]);
+        assert_ne!(d, a);
+    }
+
+    #[test]
+    fn sort_by() {
+        let mut sm = DenseSlotMap::new();
+        let mut keys: Vec<_> = (0..100).map(|i| (sm.insert((i * 37) % 100), (i * 37) % 100)).collect();
+        for (k, _) in keys.iter().step_by(7) {
+            sm.remove(*k);
+        }
+        keys.retain(|&(k, _)| sm.contains_key(k));
+
+        sm.sort_by(|a, b| b.cmp(a));
+        let mut expected: Vec<_> = keys.iter().map(|&(_, v)| v).collect();
+        expected.sort_by(|a, b| b.cmp(a));
+        assert_eq!(sm.as_slice(), &expected[..]);
+        for &(k, v) in &keys {
+            assert_eq!(sm[k], v);
+        }
+        for (&k, &v) in sm.keys_as_slice().iter().zip(sm.as_slice()) {
+            assert_eq!(sm.get(k), Some(&v));
+        }
+
+        // Stable, equal values keep their relative order.
+        let before: Vec<_> = sm.keys().filter(|&k| sm[k] % 2 == 0).collect();
+        sm.sort_by_key(|v| v % 2);
+        let after: Vec<_> = sm.keys().take(before.len()).collect();
+        assert_eq!(before, after);
+
+        // A panicking comparator leaves the slot map intact.
+        let order = sm.keys_as_slice().to_vec();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut n = 0;
+            sm.sort_by(|a, b| {
+                n += 1;
+                if n == 50 {
+                    panic!("cmp");
+                }
+                a.cmp(b)
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(sm.keys_as_slice(), &order[..]);
+        for &(k, v) in &keys {
+            assert_eq!(sm[k], v);
+        }
+    }
+
+    #[test]
+    fn remove_stable() {
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+
+        for &i in &[5, 0, 19, 10] {
+            assert_eq!(sm.remove_stable(keys[i]), Some(i));
+            assert_eq!(sm.remove_stable(keys[i]), None);
+        }
+        let expected: Vec<_> = (0..20).filter(|i| ![0, 5, 10, 19].contains(i)).collect();
+        assert_eq!(sm.as_slice(), &expected[..]);
+        for (i, &k) in keys.iter().enumerate() {
+            assert_eq!(sm.get(k), if expected.contains(&i) { Some(&i) } else { None });
+        }
+
+        // Freed slots are reused like after a normal removal.
+        let k = sm.insert(100);
+        assert_eq!(k.data().idx, keys[10].data().idx);
+        assert!(!sm.contains_key(keys[10]));
+        assert_eq!(sm.as_slice().last(), Some(&100));
+    }
+
+    #[test]
+    fn into_vec_drops_once() {
+        let drops = core::cell::RefCell::new(0usize);
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|_| sm.insert(CountDrop(&drops))).collect();
+        sm.remove(keys[3]);
+        assert_eq!(*drops.borrow(), 1);
+
+        let ptr = sm.as_slice().as_ptr();
+        let values = sm.clone().into_vec();
+        assert_eq!(values.len(), 9);
+        drop(values);
+        assert_eq!(*drops.borrow(), 10);
+
+        let (ks, values) = sm.into_vecs();
+        assert_eq!(values.as_ptr(), ptr);
+        assert_eq!(ks.len(), 9);
+        assert!(!ks.contains(&keys[3]));
+        drop(values);
+        assert_eq!(*drops.borrow(), 19);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter() {
+        use rayon::prelude::*;
+
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..10_000u64).map(|i| sm.insert(i)).collect();
+        for &k in keys.iter().step_by(3) {
+            sm.remove(k);
+        }
+
+        let seq: Vec<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+        let par: Vec<_> = sm.par_iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(seq, par);
+        assert_eq!(sm.par_values().sum::<u64>(), sm.values().sum::<u64>());
+
+        sm.par_values_mut().for_each(|v| *v += 1);
+        for (k, v) in seq {
+            assert_eq!(sm[k], v + 1);
+        }
+    }
+
+    #[test]
+    fn capacity_no_realloc() {
+        let mut sm = DenseSlotMap::with_capacity(100);
+        let cap = sm.capacity();
+        assert!(cap >= 100);
+        let ptrs = |sm: &DenseSlotMap<DefaultKey, usize>| {
+            (sm.as_slice().as_ptr(), sm.keys.as_ptr(), sm.slots.as_ptr())
+        };
+        let before = ptrs(&sm);
+        let keys: Vec<_> = (0..cap).map(|i| sm.insert(i)).collect();
+        assert_eq!(ptrs(&sm), before);
+        assert_eq!(sm.capacity(), cap);
+
+        // Vacant slots count towards the capacity as well.
+        for &k in &keys[..cap / 2] {
+            sm.remove(k);
+        }
+        sm.reserve(cap);
+        let cap = sm.capacity();
+        let before = ptrs(&sm);
+        while sm.len() < cap {
+            sm.insert(0);
+        }
+        assert_eq!(ptrs(&sm), before);
+    }
+
+    #[test]
+    fn swap_remove() {
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..4).map(|i| sm.insert(i)).collect();
+
+        // The last value moves into the hole, its key still finds it.
+        assert_eq!(sm.remove(keys[0]), Some(0));
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![3, 1, 2]);
+        assert_eq!(sm.keys().collect::<Vec<_>>(), vec![keys[3], keys[1], keys[2]]);
+        assert!(!sm.contains_key(keys[0]));
+        assert_eq!(sm.get(keys[0]), None);
+        for (i, &k) in keys.iter().enumerate().skip(1) {
+            assert_eq!(sm[k], i);
+        }
+
+        // Removing the last value moves nothing.
+        assert_eq!(sm.remove(keys[2]), Some(2));
+        assert_eq!(sm.iter().collect::<Vec<_>>(), vec![(keys[3], &3), (keys[1], &1)]);
+
+        // Reused slots get a new version.
+        let k = sm.insert(4);
+        assert_eq!(k.data().idx, keys[2].data().idx);
+        assert_ne!(k, keys[2]);
+        *sm.get_mut(k).unwrap() += 1;
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![3, 1, 5]);
+        assert_eq!(sm.as_slice(), &[3, 1, 5]);
+        sm.values_mut_slice().iter_mut().for_each(|v| *v *= 10);
+        assert_eq!(sm[k], 50);
+        assert_eq!(sm.keys_as_slice(), &[keys[3], keys[1], k]);
+        assert_eq!(sm.key_at_dense_index(2), Some(k));
+        assert_eq!(sm.key_at_dense_index(3), None);
+    }
+
+    quickcheck! {
+        fn qc_double_ended_equiv_vec(values: Vec<u32>, removals: Vec<usize>, ends: Vec<bool>) -> bool {
+            let mut sm = DenseSlotMap::new();
+            let keys: Vec<_> = values.iter().map(|v| sm.insert(*v)).collect();
+            for r in removals {
+                if !keys.is_empty() {
+                    sm.remove(keys[r % keys.len()]);
+                }
+            }
+
+            // The model is whatever forward iteration yields.
+            let mut model: std::collections::VecDeque<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+            let mut iter = sm.clone().into_iter();
+            // repeat_n needs a newer Rust than the minimum supported one.
+            #[allow(clippy::manual_repeat_n)]
+            let tail = core::iter::repeat(false).take(model.len() + 1);
+            for back in ends.into_iter().chain(tail) {
+                let (expected, actual) = if back {
+                    (model.pop_back(), iter.next_back())
+                } else {
+                    (model.pop_front(), iter.next())
+                };
+                if actual != expected || iter.len() != model.len() {
+                    return false;
+                }
+            }
+
+            let fwd: Vec<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+            let mut rev: Vec<_> = sm.iter_mut().rev().map(|(k, v)| (k, *v)).collect();
+            rev.reverse();
+            let mut values_rev: Vec<_> = sm.values().rev().copied().collect();
+            values_rev.reverse();
+            let mut keys_rev: Vec<_> = sm.keys().rev().collect();
+            keys_rev.reverse();
+            rev == fwd && values_rev == sm.as_slice() && keys_rev == sm.keys().collect::<Vec<_>>()
+        }
+    }
+
     quickcheck! {
         fn qc_slotmap_equiv_hashmap(operations: Vec<(u8, u32)>) -> bool {
             let mut hm = HashMap::new();
@@ -1377,6 +2225,65 @@ mod tests {
         assert_eq!(smkv, dekv);
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_held_keys() {
+        let mut sm = DenseSlotMap::new();
+        let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+        for k in keys.iter().step_by(3) {
+            sm.remove(*k);
+        }
+
+        let ser = serde_json::to_string(&sm).unwrap();
+        let mut de: DenseSlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+        for &k in &keys {
+            assert_eq!(de.get(k), sm.get(k));
+        }
+
+        // Reusing the vacant slots never revives a stale key.
+        let new: Vec<_> = (0..10).map(|i| de.insert(100 + i)).collect();
+        for &k in &keys {
+            assert_eq!(de.get(k), sm.get(k));
+        }
+        assert!(new.iter().all(|k| !keys.contains(k)));
+    }
+
+    #[test]
+    fn version_wraps_past_sentinel_version() {
+        let mut sm: DenseSlotMap<DefaultKey, i32> = DenseSlotMap::new();
+        sm.insert(1);
+        sm.slots[1].version = u32::MAX;
+        let old: DefaultKey = KeyData::new(1, u32::MAX).into();
+        assert_eq!(sm.remove(old), Some(1));
+        assert_eq!(sm.slots[1].version, 2);
+
+        let new = sm.insert(2);
+        assert_eq!(new.data().version.get(), 3);
+        assert_eq!(sm.get(old), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_malicious() {
+        type Sm = DenseSlotMap<DefaultKey, i32>;
+        let inconsistent = r#"[{"value":null,"version":0},{"value":5,"version":2}]"#;
+        assert!(serde_json::from_str::<Sm>(inconsistent).is_err());
+        let missing = r#"[{"value":null,"version":0},{"value":null,"version":3}]"#;
+        assert!(serde_json::from_str::<Sm>(missing).is_err());
+        let occupied_sentinel = r#"[{"value":1,"version":1}]"#;
+        assert!(serde_json::from_str::<Sm>(occupied_sentinel).is_err());
+        assert!(serde_json::from_str::<Sm>("[]").is_err());
+        let zero_version = r#"[{"value":null,"version":0},{"value":null,"version":0},{"value":7,"version":5}]"#;
+        assert!(serde_json::from_str::<Sm>(zero_version).is_err());
+
+        let ok = r#"[{"value":null,"version":0},{"value":null,"version":2},{"value":7,"version":5}]"#;
+        let mut sm: Sm = serde_json::from_str(ok).unwrap();
+        assert_eq!(sm.len(), 1);
+        let k = sm.insert(8);
+        assert_eq!((k.data().idx, k.data().version.get()), (1, 3));
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![7, 8]);
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn slotmap_serde_freelist() {
@@ -18,7 +18,7 @@
 use alloc::collections::TryReserveError;
 use alloc::vec::Vec;
 use core::fmt;
-use core::iter::FusedIterator;
+use core::iter::{Extend, FromIterator, FusedIterator};
 use core::marker::PhantomData;
 use core::mem::ManuallyDrop;
 #[allow(unused_imports)] // MaybeUninit is only used on nightly at the moment.
@@ -26,20 +26,27 @@ use core::mem::MaybeUninit;
 use core::ops::{Index, IndexMut};
 
 use crate::util::{Never, UnwrapUnchecked};
-use crate::{DefaultKey, Key, KeyData};
+use crate::{DefaultKey, InvariantError, Key, KeyData, KeyError, ReusePolicy, SlotMap};
 
-// Metadata to maintain the freelist.
+// Metadata of a contiguous block of vacant slots. The blocks form a circular
+// doubly linked list through the sentinel block 0, whose front is the sentinel
+// slot. Links are indices into the block list, front and back are slots.
 #[derive(Clone, Copy, Debug)]
-struct FreeListEntry {
+struct Block {
+    front: u32,
+    back: u32,
     next: u32,
     prev: u32,
-    other_end: u32,
 }
 
-// Storage inside a slot or metadata for the freelist when vacant.
+// Storage inside a slot or, for a vacant slot, the index of a block. Only the
+// front and back slot of a vacant block store the index of their block, but
+// every vacant slot is set to some index, so copying one reads no leftover
+// bytes of a value. Keeping the block metadata out of the slots means a slot
+// is no larger than in a SlotMap.
 union SlotUnion<T> {
     value: ManuallyDrop<T>,
-    free: FreeListEntry,
+    block: u32,
 }
 
 // A slot, which represents storage for a value and a current version.
@@ -52,12 +59,12 @@ struct Slot<T> {
 // Safe API to read a slot.
 enum SlotContent<'a, T: 'a> {
     Occupied(&'a T),
-    Vacant(&'a FreeListEntry),
+    Vacant(&'a u32),
 }
 
 enum SlotContentMut<'a, T: 'a> {
     OccupiedMut(&'a mut T),
-    VacantMut(&'a mut FreeListEntry),
+    VacantMut(&'a mut u32),
 }
 
 use self::SlotContent::{Occupied, Vacant};
@@ -75,7 +82,7 @@ impl<T> Slot<T> {
             if self.occupied() {
                 Occupied(&*self.u.value)
             } else {
-                Vacant(&self.u.free)
+                Vacant(&self.u.block)
             }
         }
     }
@@ -85,7 +92,7 @@ impl<T> Slot<T> {
             if self.occupied() {
                 OccupiedMut(&mut *self.u.value)
             } else {
-                VacantMut(&mut self.u.free)
+                VacantMut(&mut self.u.block)
             }
         }
     }
@@ -109,7 +116,7 @@ impl<T: Clone> Clone for Slot<T> {
                 Occupied(value) => SlotUnion {
                     value: ManuallyDrop::new(value.clone()),
                 },
-                Vacant(&free) => SlotUnion { free },
+                Vacant(&block) => SlotUnion { block },
             },
             version: self.version,
         }
@@ -118,13 +125,13 @@ impl<T: Clone> Clone for Slot<T> {
     fn clone_from(&mut self, source: &Self) {
         match (self.get_mut(), source.get()) {
             (OccupiedMut(self_val), Occupied(source_val)) => self_val.clone_from(source_val),
-            (VacantMut(self_free), Vacant(&source_free)) => *self_free = source_free,
+            (VacantMut(self_block), Vacant(&source_block)) => *self_block = source_block,
             (_, Occupied(value)) => {
                 self.u = SlotUnion {
                     value: ManuallyDrop::new(value.clone()),
                 }
             },
-            (_, Vacant(&free)) => self.u = SlotUnion { free },
+            (_, Vacant(&block)) => self.u = SlotUnion { block },
         }
         self.version = source.version;
     }
@@ -136,7 +143,7 @@ impl<T: fmt::Debug> fmt::Debug for Slot<T> {
         builder.field("version", &self.version);
         match self.get() {
             Occupied(value) => builder.field("value", value).finish(),
-            Vacant(free) => builder.field("free", free).finish(),
+            Vacant(block) => builder.field("block", block).finish(),
         }
     }
 }
@@ -144,10 +151,27 @@ impl<T: fmt::Debug> fmt::Debug for Slot<T> {
 /// Hop slot map, storage with stable unique keys.
 ///
 /// See [crate documentation](crate) for more details.
-#[derive(Debug)]
+///
+/// # Iteration order
+///
+/// All iterators visit elements in ascending order of their slot index, and
+/// in descending order when iterated from the back, like
+/// [`SlotMap`](crate::SlotMap). Hopping over vacant blocks does not change
+/// this order. Which slot an element is stored in only depends on the
+/// operations performed on the slot map, so two slot maps with identical
+/// histories of operations iterate in identical order, and a clone continues
+/// with the same history. Deserialization keeps every element in its slot,
+/// but rebuilds the blocks of vacant slots, which may place new elements
+/// differently than in the original slot map.
+///
+/// Which vacant slot is reused by the next insertion is determined by the
+/// [`ReusePolicy`] of the slot map, see
+/// [`set_reuse_policy`](Self::set_reuse_policy).
 pub struct HopSlotMap<K: Key, V> {
     slots: Vec<Slot<V>>,
+    blocks: Vec<Block>,
     num_elems: u32,
+    policy: ReusePolicy,
     _k: PhantomData<fn(K) -> K>,
 }
 
@@ -215,26 +239,70 @@ impl<K: Key, V> HopSlotMap<K, V> {
     /// let hello = messages.insert("Hello");
     /// ```
     pub fn with_capacity_and_key(capacity: usize) -> Self {
-        // Create slots with sentinel at index 0.
+        // Create slots with sentinel at index 0, in the sentinel block.
         let mut slots = Vec::with_capacity(capacity + 1);
         slots.push(Slot {
-            u: SlotUnion {
-                free: FreeListEntry {
-                    next: 0,
-                    prev: 0,
-                    other_end: 0,
-                },
-            },
+            u: SlotUnion { block: 0 },
             version: 0,
         });
+        let blocks = alloc::vec![Block {
+            front: 0,
+            back: 0,
+            next: 0,
+            prev: 0,
+        }];
 
         Self {
             slots,
+            blocks,
             num_elems: 0,
+            policy: ReusePolicy::Fifo,
             _k: PhantomData,
         }
     }
 
+    /// Returns the [`ReusePolicy`] of the slot map. New slot maps use
+    /// [`ReusePolicy::Fifo`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let sm: HopSlotMap<_, i32> = HopSlotMap::new();
+    /// assert_eq!(sm.reuse_policy(), ReusePolicy::Fifo);
+    /// ```
+    pub fn reuse_policy(&self) -> ReusePolicy {
+        self.policy
+    }
+
+    /// Sets the order in which vacant slots are reused by future insertions.
+    ///
+    /// The freelist of a [`HopSlotMap`] consists of blocks of contiguous
+    /// vacant slots, so the policy applies to blocks: with
+    /// [`ReusePolicy::Fifo`] the oldest block is reused first, with
+    /// [`ReusePolicy::Lifo`] the newest. A slot vacated next to an existing
+    /// block joins that block and takes its age. Within a block slots are
+    /// always reused from the back. The policy is not serialized, a
+    /// deserialized slot map uses [`ReusePolicy::Fifo`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// sm.set_reuse_policy(ReusePolicy::Lifo);
+    /// let keys: Vec<_> = (0..5).map(|i| sm.insert(i)).collect();
+    /// sm.remove(keys[1]);
+    /// sm.remove(keys[3]);
+    ///
+    /// // The slot of keys[3] was vacated last, so it is reused first.
+    /// sm.insert(10);
+    /// assert_eq!(sm.values().copied().collect::<Vec<_>>(), [0, 2, 10, 4]);
+    /// ```
+    pub fn set_reuse_policy(&mut self, policy: ReusePolicy) {
+        self.policy = policy;
+    }
+
     /// Returns the number of elements in the slot map.
     ///
     /// # Examples
@@ -305,6 +373,34 @@ impl<K: Key, V> HopSlotMap<K, V> {
         self.slots.reserve(needed);
     }
 
+    /// Reserves the minimum capacity for at least `additional` more elements
+    /// to be inserted in the [`HopSlotMap`]. Vacant slots that can be reused count
+    /// towards `additional`.
+    ///
+    /// Like [`Vec::reserve_exact`] this does not deliberately over-allocate,
+    /// but the allocator may still give the collection more space than
+    /// requested. Prefer [`reserve`](Self::reserve) if future insertions are
+    /// expected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new allocation size overflows [`usize`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// sm.insert("foo");
+    /// sm.reserve_exact(32);
+    /// assert!(sm.capacity() >= 33);
+    /// ```
+    pub fn reserve_exact(&mut self, additional: usize) {
+        // One slot is reserved for the freelist sentinel.
+        let needed = (self.len() + additional).saturating_sub(self.slots.len() - 1);
+        self.slots.reserve_exact(needed);
+    }
+
     /// Tries to reserve capacity for at least `additional` more elements to be
     /// inserted in the [`HopSlotMap`]. The collection may reserve more space to
     /// avoid frequent reallocations.
@@ -326,6 +422,136 @@ impl<K: Key, V> HopSlotMap<K, V> {
         self.slots.try_reserve(needed)
     }
 
+    /// Shrinks the capacity of the slot map as much as possible.
+    ///
+    /// A trailing block of vacant slots is released as well. Keys that pointed
+    /// into those slots stay invalid. The slot map remembers the highest
+    /// version it released, and slots that are created again at those indices
+    /// start past that version. Vacant blocks followed by an occupied slot can
+    /// not be released, because their versions are still needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::with_capacity(100);
+    /// let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+    /// for k in &keys[10..] {
+    ///     sm.remove(*k);
+    /// }
+    /// sm.shrink_to_fit();
+    /// assert!(sm.capacity() < 100);
+    ///
+    /// let k = sm.insert(42);
+    /// assert_eq!(sm.get(keys[10]), None);
+    /// assert_eq!(sm[k], 42);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        let last = self.slots.len() - 1;
+        if !self.slots[last].occupied() {
+            // All unsafe accesses here are safe due to the invariants of the
+            // slot map freelist, last is the back of the trailing block.
+            unsafe {
+                let id = self.block_at(last as u32);
+                let front = self.block(id).front;
+                let new_len = (front as usize).max(1);
+                let released_version = self.slots[new_len..]
+                    .iter()
+                    .map(|slot| slot.version)
+                    .fold(self.slots[0].version, u32::max);
+
+                if front == 0 {
+                    // The trailing block starts at the sentinel, which stays.
+                    self.block(0).back = 0;
+                } else {
+                    self.remove_block(id);
+                }
+
+                self.slots.truncate(new_len);
+                self.slots[0].version = released_version;
+            }
+        }
+
+        self.slots.shrink_to_fit();
+        self.blocks.shrink_to_fit();
+    }
+
+    /// Moves all elements into the lowest slots and releases the storage of
+    /// the vacant slots, so the slot map holds no vacant slots afterwards and
+    /// iteration visits a single contiguous run of slots.
+    ///
+    /// The key of every moved element changes. For each moved element
+    /// `remap(old_key, new_key, &mut value)` is called so references to it can
+    /// be updated, elements that stay in place are not passed to `remap`.
+    /// After compacting, keys of moved elements are invalid, just like keys
+    /// of removed elements. They never alias the element now stored in their
+    /// old slot, even once the slot is used again.
+    ///
+    /// This function must iterate over all slots, empty or not.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+    /// for k in &keys[..8] {
+    ///     sm.remove(*k);
+    /// }
+    ///
+    /// let mut moved = Vec::new();
+    /// sm.compact(|old, new, _| moved.push((old, new)));
+    /// assert_eq!(moved.len(), 2);
+    /// assert!(sm.capacity() < 10);
+    /// for (old, new) in moved {
+    ///     assert!(!sm.contains_key(old));
+    ///     assert!(sm.contains_key(new));
+    /// }
+    /// ```
+    pub fn compact<F>(&mut self, mut remap: F)
+    where
+        F: FnMut(K, K, &mut V),
+    {
+        // Move elements from the back into vacant slots at the front, and
+        // collect the moves so remap only runs once the slot map is
+        // consistent again.
+        let mut moves = Vec::new();
+        let mut lo = 1;
+        let mut hi = self.slots.len() - 1;
+        while lo < hi {
+            if self.slots[lo].occupied() {
+                lo += 1;
+            } else if !self.slots[hi].occupied() {
+                hi -= 1;
+            } else {
+                let (front, back) = self.slots.split_at_mut(hi);
+                let (dst, src) = (&mut front[lo], &mut back[0]);
+                let old_key = KeyData::new(hi as u32, src.version).into();
+                unsafe {
+                    let value = ManuallyDrop::take(&mut src.u.value);
+                    src.u.block = 0;
+                    src.version = src.version.wrapping_add(1).max(2);
+                    dst.u.value = ManuallyDrop::new(value);
+                    dst.version |= 1;
+                }
+                moves.push((old_key, lo));
+            }
+        }
+
+        // All vacant slots form a single block at the back now, shrink_to_fit
+        // releases it and guards the versions of its slots against reuse.
+        if !moves.is_empty() {
+            self.rebuild_freelist();
+        }
+        self.shrink_to_fit();
+
+        for (old_key, idx) in moves {
+            let slot = &mut self.slots[idx];
+            let new_key = KeyData::new(idx as u32, slot.version).into();
+            remap(old_key, new_key, unsafe { &mut *slot.u.value });
+        }
+    }
+
     /// Returns [`true`] if the slot map contains `key`.
     ///
     /// # Examples
@@ -363,14 +589,99 @@ impl<K: Key, V> HopSlotMap<K, V> {
     /// ```
     #[inline(always)]
     pub fn insert(&mut self, value: V) -> K {
-        unsafe { self.try_insert_with_key::<_, Never>(move |_| Ok(value)).unwrap_unchecked_() }
+        match self.try_insert(value) {
+            Ok(key) => key,
+            Err(_) => panic!("HopSlotMap number of elements overflow"),
+        }
+    }
+
+    /// Inserts a value into the slot map. Returns a unique key that can be
+    /// used to access this value.
+    ///
+    /// If the number of elements in the slot map equals 2<sup>32</sup> - 2
+    /// the map is full, and the value is handed back in `Err` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// match sm.try_insert(42) {
+    ///     Ok(key) => assert_eq!(sm[key], 42),
+    ///     Err(value) => println!("slot map is full, dropping {}", value),
+    /// }
+    /// ```
+    #[inline(always)]
+    pub fn try_insert(&mut self, value: V) -> Result<K, V> {
+        if self.is_full() {
+            return Err(value);
+        }
+
+        Ok(unsafe { self.try_insert_with_key::<_, Never>(move |_| Ok(value)).unwrap_unchecked_() })
+    }
+
+    // Returns true if inserting another element would exceed the maximum
+    // number of elements.
+    #[inline(always)]
+    fn is_full(&self) -> bool {
+        // New slots are only pushed when there are no vacant slots, so the
+        // number of slots can not overflow before the number of elements.
+        self.num_elems + 1 == u32::MAX
     }
 
     // Helper function to make using the freelist painless.
     // For that same ergonomy it uses u32, not usize as index.
-    // Safe iff idx is a valid index and the slot at that index is vacant.
-    unsafe fn freelist(&mut self, idx: u32) -> &mut FreeListEntry {
-        &mut self.slots.get_unchecked_mut(idx as usize).u.free
+    // Safe iff id is a valid block index.
+    unsafe fn block(&mut self, id: u32) -> &mut Block {
+        self.blocks.get_unchecked_mut(id as usize)
+    }
+
+    // Returns the block a vacant slot at either end of a block belongs to.
+    #[inline(always)]
+    unsafe fn block_at(&self, idx: u32) -> u32 {
+        self.slots.get_unchecked(idx as usize).u.block
+    }
+
+    // Marks a vacant slot as an end of the given block.
+    #[inline(always)]
+    unsafe fn set_block_at(&mut self, idx: u32, id: u32) {
+        self.slots.get_unchecked_mut(idx as usize).u.block = id;
+    }
+
+    // Adds a new block at the tail of the freelist.
+    unsafe fn push_block(&mut self, front: u32, back: u32) {
+        let id = self.blocks.len() as u32;
+        let old_tail = self.block(0).prev;
+        self.blocks.push(Block {
+            front,
+            back,
+            next: 0,
+            prev: old_tail,
+        });
+        self.block(old_tail).next = id;
+        self.block(0).prev = id;
+        self.set_block_at(front, id);
+        self.set_block_at(back, id);
+    }
+
+    // Unlinks a block from the freelist and moves the last block into its
+    // place, so the block list stays dense. The slots of the removed block are
+    // not touched.
+    unsafe fn remove_block(&mut self, id: u32) {
+        let Block { next, prev, .. } = *self.block(id);
+        self.block(prev).next = next;
+        self.block(next).prev = prev;
+
+        let last = self.blocks.len() as u32 - 1;
+        if id != last {
+            let moved = *self.block(last);
+            *self.block(id) = moved;
+            self.block(moved.prev).next = id;
+            self.block(moved.next).prev = id;
+            self.set_block_at(moved.front, id);
+            self.set_block_at(moved.back, id);
+        }
+        self.blocks.pop();
     }
 
     /// Inserts a value given by `f` into the slot map. The key where the
@@ -419,30 +730,36 @@ impl<K: Key, V> HopSlotMap<K, V> {
     ///
     /// sm.try_insert_with_key::<_, ()>(|k| Err(())).unwrap_err();
     /// ```
+    #[inline(always)]
     pub fn try_insert_with_key<F, E>(&mut self, f: F) -> Result<K, E>
     where
         F: FnOnce(K) -> Result<V, E>,
     {
         // In case f panics, we don't make any changes until we have the value.
-        let new_num_elems = self.num_elems + 1;
-        if new_num_elems == core::u32::MAX {
+        if self.is_full() {
             panic!("HopSlotMap number of elements overflow");
         }
+        let new_num_elems = self.num_elems + 1;
 
         // All unsafe accesses here are safe due to the invariants of the slot
         // map freelist.
         unsafe {
-            let head = self.freelist(0).next;
+            // Blocks are added at the tail, so the head is the oldest block.
+            let id = match self.policy {
+                ReusePolicy::Fifo => self.block(0).next,
+                ReusePolicy::Lifo => self.block(0).prev,
+            };
 
-            // We have a contiguous block of vacant slots starting at head.
+            // We have a contiguous block of vacant slots from front to back.
             // Put our new element at the back slot.
-            let front = head;
-            let back = self.freelist(front).other_end;
+            let Block { front, back, .. } = *self.block(id);
             let slot_idx = back as usize;
 
             // Freelist is empty.
             if slot_idx == 0 {
-                let version = 1;
+                // New slots start past any version handed out for a released
+                // slot.
+                let version = self.slots.get_unchecked(0).version | 1;
                 let key = KeyData::new(self.slots.len() as u32, version).into();
 
                 self.slots.push(Slot {
@@ -462,15 +779,13 @@ impl<K: Key, V> HopSlotMap<K, V> {
 
             // Update freelist.
             if front == back {
-                // Used last slot in this block, move next one to head.
-                let new_head = self.freelist(front).next;
-                self.freelist(0).next = new_head;
-                self.freelist(new_head).prev = 0;
+                // Used last slot in this block, remove it.
+                self.remove_block(id);
             } else {
-                // Continue using this block, only need to update other_ends.
+                // Continue using this block, only need to update its back.
                 let new_back = back - 1;
-                self.freelist(new_back).other_end = front;
-                self.freelist(front).other_end = new_back;
+                self.block(id).back = new_back;
+                self.set_block_at(new_back, id);
             }
 
             // And finally insert the value.
@@ -482,73 +797,325 @@ impl<K: Key, V> HopSlotMap<K, V> {
         }
     }
 
+    /// Inserts all values from `iter` into the slot map, returning the keys
+    /// in the same order as the values. Vacant slots are reused exactly as
+    /// they would be by repeated calls to [`insert`](Self::insert), but
+    /// capacity is reserved only once up front and the freelist is updated
+    /// once per block of vacant slots rather than once per value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of elements in the slot map would exceed
+    /// 2<sup>32</sup> - 2.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let keys = sm.insert_from_iter(vec!["a", "b", "c"]);
+    /// assert_eq!(keys.len(), 3);
+    /// assert_eq!(sm[keys[1]], "b");
+    /// ```
+    pub fn insert_from_iter<I>(&mut self, iter: I) -> Vec<K>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        // Updates the block being filled when dropped, so the slot map is
+        // consistent even if the iterator panics.
+        struct Fill<'a, K: Key, V> {
+            sm: &'a mut HopSlotMap<K, V>,
+            id: u32,
+            front: u32,
+            back: u32,
+            cur: u32,
+        }
+
+        impl<'a, K: Key, V> Drop for Fill<'a, K, V> {
+            fn drop(&mut self) {
+                let (front, cur) = (self.front, self.cur);
+                if cur == self.back {
+                    return;
+                }
+
+                // Same as in try_insert_with_key, for all filled slots at once.
+                unsafe {
+                    if front > 0 && cur < front {
+                        self.sm.remove_block(self.id);
+                    } else {
+                        self.sm.block(self.id).back = cur;
+                        self.sm.set_block_at(cur, self.id);
+                    }
+                }
+            }
+        }
+
+        // Updates the number of elements for the pushed slots when dropped,
+        // so the slot map is consistent even if the iterator panics.
+        struct Push<'a, K: Key, V> {
+            sm: &'a mut HopSlotMap<K, V>,
+            start: u32,
+        }
+
+        impl<'a, K: Key, V> Drop for Push<'a, K, V> {
+            fn drop(&mut self) {
+                self.sm.num_elems += self.sm.slots.len() as u32 - self.start;
+            }
+        }
+
+        let mut iter = iter.into_iter();
+        let (lower, _) = iter.size_hint();
+        self.reserve(lower);
+        let mut keys = Vec::with_capacity(lower);
+
+        loop {
+            // Pick blocks exactly like try_insert_with_key, and fill each one
+            // from the back.
+            let (id, Block { front, back, .. }) = unsafe {
+                let id = match self.policy {
+                    ReusePolicy::Fifo => self.block(0).next,
+                    ReusePolicy::Lifo => self.block(0).prev,
+                };
+                (id, *self.block(id))
+            };
+            if back == 0 {
+                break;
+            }
+
+            let mut fill = Fill {
+                sm: self,
+                id,
+                front,
+                back,
+                cur: back,
+            };
+            while fill.cur >= front.max(1) {
+                let value = match iter.next() {
+                    Some(value) => value,
+                    None => return keys,
+                };
+                if fill.sm.is_full() {
+                    panic!("HopSlotMap number of elements overflow");
+                }
+
+                let slot = &mut fill.sm.slots[fill.cur as usize];
+                slot.version |= 1;
+                slot.u.value = ManuallyDrop::new(value);
+                keys.push(KeyData::new(fill.cur, slot.version).into());
+                fill.sm.num_elems += 1;
+                fill.cur -= 1;
+            }
+        }
+
+        // The freelist is empty, the remaining values go into new slots, all
+        // pushed in one go.
+        let version = self.slots[0].version | 1;
+        let start = self.slots.len() as u32;
+        let mut room = u32::MAX - 1 - self.num_elems;
+        let push = Push { sm: self, start };
+        push.sm.slots.extend(iter.map(|value| {
+            if room == 0 {
+                panic!("HopSlotMap number of elements overflow");
+            }
+            room -= 1;
+            Slot {
+                u: SlotUnion {
+                    value: ManuallyDrop::new(value),
+                },
+                version,
+            }
+        }));
+        let end = push.sm.slots.len() as u32;
+        drop(push);
+
+        keys.extend((start..end).map(|idx| K::from(KeyData::new(idx, version))));
+        keys
+    }
+
     // Helper function to remove a value from a slot. Safe iff the slot is
     // occupied. Returns the value removed.
     #[inline(always)]
     unsafe fn remove_from_slot(&mut self, idx: usize) -> V {
         // Remove value from slot.
         let slot = self.slots.get_unchecked_mut(idx);
-        slot.version = slot.version.wrapping_add(1);
+        // Version 0 is reserved for the sentinel, skip it when wrapping around.
+        slot.version = slot.version.wrapping_add(1).max(2);
         let value = ManuallyDrop::take(&mut slot.u.value);
+        slot.u.block = 0;
 
+        // Maintain freelist.
+        self.link_vacant(idx as u32, idx as u32);
+        self.num_elems -= 1;
+
+        value
+    }
+
+    // Helper function to add a run of vacant slots from front to back
+    // inclusive to the freelist. Safe iff all slots in the run are vacant and
+    // not yet on the freelist, while the slots around them are.
+    #[inline(always)]
+    unsafe fn link_vacant(&mut self, front: u32, back: u32) {
         // This is safe and can't underflow because of the sentinel element at
         // the start.
-        let left_vacant = !self.slots.get_unchecked(idx - 1).occupied();
-        let right_vacant = self.slots.get(idx + 1).map_or(false, |s| !s.occupied());
+        let left_vacant = !self.slots.get_unchecked(front as usize - 1).occupied();
+        let right_vacant = matches!(self.slots.get(back as usize + 1), Some(s) if !s.occupied());
 
-        // Maintain freelist by either appending/prepending this slot to a
+        // Maintain freelist by either appending/prepending the run to a
         // contiguous block to the left or right, merging the two blocks to the
         // left and right or inserting a new block.
-        let i = idx as u32;
         match (left_vacant, right_vacant) {
             (false, false) => {
                 // New block, insert it at the tail.
-                let old_tail = self.freelist(0).prev;
-                self.freelist(0).prev = i;
-                self.freelist(old_tail).next = i;
-                *self.freelist(i) = FreeListEntry {
-                    other_end: i,
-                    next: 0,
-                    prev: old_tail,
-                };
+                self.push_block(front, back);
             },
 
             (false, true) => {
                 // Prepend to vacant block on right.
-                let front_data = *self.freelist(i + 1);
-
-                // Since the start of this block moved we must update the pointers to it.
-                self.freelist(front_data.other_end).other_end = i;
-                self.freelist(front_data.prev).next = i;
-                self.freelist(front_data.next).prev = i;
-                *self.freelist(i) = front_data;
+                let id = self.block_at(back + 1);
+                self.block(id).front = front;
+                self.set_block_at(front, id);
             },
 
             (true, false) => {
                 // Append to vacant block on left.
-                let front = self.freelist(i - 1).other_end;
-                self.freelist(i).other_end = front;
-                self.freelist(front).other_end = i;
+                let id = self.block_at(front - 1);
+                self.block(id).back = back;
+                self.set_block_at(back, id);
             },
 
             (true, true) => {
-                // We must merge left and right.
-                // First snip right out of the freelist.
-                let right = *self.freelist(i + 1);
-                self.freelist(right.prev).next = right.next;
-                self.freelist(right.next).prev = right.prev;
-
-                // Now update endpoints.
-                let front = self.freelist(i - 1).other_end;
-                let back = right.other_end;
-                self.freelist(front).other_end = back;
-                self.freelist(back).other_end = front;
+                // We must merge left and right. Extend left over right, then
+                // snip right out of the freelist.
+                let left = self.block_at(front - 1);
+                let right = self.block_at(back + 1);
+                let right_back = self.block(right).back;
+                self.block(left).back = right_back;
+                self.set_block_at(right_back, left);
+                self.remove_block(right);
             },
         }
+    }
 
-        self.num_elems -= 1;
+    // Rebuilds the freelist and element count from scratch, based only on
+    // the versions of the slots. Any freelist data in vacant slots is
+    // overwritten, so they may contain garbage beforehand. The sentinel slot
+    // must be vacant.
+    fn rebuild_freelist(&mut self) {
+        // The sentinel is always vacant, so the first block is its block.
+        self.blocks.clear();
+        self.blocks.push(Block {
+            front: 0,
+            back: 0,
+            next: 0,
+            prev: 0,
+        });
 
-        value
+        let mut num_elems = 0;
+        let mut i = 0;
+        while i < self.slots.len() {
+            // i is the start of a contiguous block of vacant slots.
+            let front = i;
+            while i < self.slots.len() && !self.slots[i].occupied() {
+                i += 1;
+            }
+            let back = i - 1;
+
+            // Update freelist.
+            unsafe {
+                if front == 0 {
+                    self.block(0).back = back as u32;
+                    self.set_block_at(0, 0);
+                    self.set_block_at(back as u32, 0);
+                } else {
+                    self.push_block(front as u32, back as u32);
+                }
+            }
+
+            // Skip occupied slots.
+            while i < self.slots.len() && self.slots[i].occupied() {
+                num_elems += 1;
+                i += 1;
+            }
+        }
+
+        self.num_elems = num_elems;
+    }
+
+    // Takes the slot map apart into the version and value of every slot, the
+    // sentinel included.
+    pub(crate) fn into_slot_parts(self) -> impl Iterator<Item = (u32, Option<V>)> {
+        self.slots.into_iter().map(|mut slot| {
+            let version = slot.version;
+            let value = if slot.occupied() {
+                // Mark the slot vacant so dropping it doesn't drop the value.
+                slot.version = 0;
+                Some(unsafe { ManuallyDrop::take(&mut slot.u.value) })
+            } else {
+                None
+            };
+            (version, value)
+        })
+    }
+
+    // Builds a slot map from the parts given by into_slot_parts of either
+    // slot map, keeping the index and version of every slot.
+    pub(crate) fn from_slot_parts(parts: impl Iterator<Item = (u32, Option<V>)>) -> Self {
+        let slots = parts
+            .map(|(version, value)| Slot {
+                u: match value {
+                    Some(value) => SlotUnion {
+                        value: ManuallyDrop::new(value),
+                    },
+                    None => SlotUnion { block: 0 },
+                },
+                version,
+            })
+            .collect();
+
+        let mut sm = Self {
+            slots,
+            blocks: Vec::new(),
+            num_elems: 0,
+            policy: ReusePolicy::Fifo,
+            _k: PhantomData,
+        };
+        sm.rebuild_freelist();
+        sm
+    }
+
+    // Panics with a message describing why key is invalid. Kept out of line
+    // so the happy path of indexing stays small.
+    #[cold]
+    #[inline(never)]
+    fn invalid_key_panic(&self, key: K) -> ! {
+        let kd = key.data();
+        match self.key_error(key) {
+            KeyError::Null => panic!("invalid HopSlotMap key Key({:?}): key is null", kd),
+            KeyError::Stale { current_version } => panic!(
+                "invalid HopSlotMap key Key({:?}): key is stale, slot currently at version {}",
+                kd, current_version
+            ),
+            _ => panic!(
+                "invalid HopSlotMap key Key({:?}): key is out of range, slot does not exist",
+                kd
+            ),
+        }
+    }
+
+    // Determines why a key that failed a lookup is invalid.
+    #[cold]
+    fn key_error(&self, key: K) -> KeyError {
+        let kd = key.data();
+        if kd.is_null() {
+            KeyError::Null
+        } else {
+            match self.slots.get(kd.idx as usize) {
+                Some(slot) => KeyError::Stale {
+                    current_version: slot.version,
+                },
+                None => KeyError::OutOfBounds,
+            }
+        }
     }
 
     /// Removes a key from the slot map, returning the value at the key if the
@@ -573,6 +1140,94 @@ impl<K: Key, V> HopSlotMap<K, V> {
         }
     }
 
+    /// Removes a key from the slot map if `f` returns true for its value,
+    /// returning the removed value. Returns `None` if the key is invalid or
+    /// `f` returned false, in the latter case the slot map is unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let key = sm.insert(42);
+    /// assert_eq!(sm.remove_if(key, |v| *v < 10), None);
+    /// assert_eq!(sm.remove_if(key, |v| *v > 10), Some(42));
+    /// assert_eq!(sm.remove_if(key, |_| true), None);
+    /// ```
+    pub fn remove_if<F>(&mut self, key: K, f: F) -> Option<V>
+    where
+        F: FnOnce(&V) -> bool,
+    {
+        let idx = key.data().idx as usize;
+        if f(self.get(key)?) {
+            // This is safe because get succeeded, so the slot is occupied.
+            Some(unsafe { self.remove_from_slot(idx) })
+        } else {
+            None
+        }
+    }
+
+    /// Removes all given keys from the slot map, returning how many elements
+    /// were actually removed. Invalid keys, including stale keys and keys that
+    /// appear more than once, are skipped.
+    ///
+    /// All elements are vacated first, after which every run of adjacent
+    /// vacated slots is added to the freelist at once. This is faster than
+    /// removing the keys one by one when they are clustered, but the order in
+    /// which the vacated slots are reused may differ.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let a = sm.insert(1);
+    /// let b = sm.insert(2);
+    /// let c = sm.insert(3);
+    /// sm.remove(c);
+    /// assert_eq!(sm.remove_many(&[a, b, a, c]), 2);
+    /// assert!(sm.is_empty());
+    /// ```
+    pub fn remove_many(&mut self, keys: &[K]) -> usize {
+        // The values are only dropped once the freelist is consistent again,
+        // in case dropping one panics.
+        let mut values = Vec::new();
+        let mut vacated = Vec::new();
+        for &key in keys {
+            if self.contains_key(key) {
+                // This is safe because we know that the slot is occupied. A
+                // duplicate key is stale after its slot is vacated.
+                let idx = key.data().idx;
+                unsafe {
+                    let slot = self.slots.get_unchecked_mut(idx as usize);
+                    slot.version = slot.version.wrapping_add(1).max(2);
+                    values.push(ManuallyDrop::take(&mut slot.u.value));
+                    slot.u.block = 0;
+                }
+                vacated.push(idx);
+            }
+        }
+
+        vacated.sort_unstable();
+        let mut i = 0;
+        while i < vacated.len() {
+            let front = vacated[i];
+            let mut back = front;
+            while vacated.get(i + 1) == Some(&(back + 1)) {
+                back += 1;
+                i += 1;
+            }
+            i += 1;
+
+            // This is safe because the slots around a maximal run of vacated
+            // slots are either occupied or on the freelist.
+            unsafe { self.link_vacant(front, back) };
+        }
+
+        self.num_elems -= vacated.len() as u32;
+        vacated.len()
+    }
+
     /// Retains only the elements specified by the predicate.
     ///
     /// In other words, remove all key-value pairs `(k, v)` such that
@@ -601,7 +1256,7 @@ impl<K: Key, V> HopSlotMap<K, V> {
         F: FnMut(K, &mut V) -> bool,
     {
         let mut elems_left_to_scan = self.len();
-        let mut cur = unsafe { self.slots.get_unchecked(0).u.free.other_end as usize + 1 };
+        let mut cur = self.blocks[0].back as usize + 1;
         while elems_left_to_scan > 0 {
             // This is safe because removing elements does not shrink slots, cur always
             // points to an occupied slot.
@@ -613,7 +1268,7 @@ impl<K: Key, V> HopSlotMap<K, V> {
 
             cur = match self.slots.get(cur + 1).map(|s| s.get()) {
                 Some(Occupied(_)) => cur + 1,
-                Some(Vacant(free)) => free.other_end as usize + 1,
+                Some(Vacant(&id)) => self.blocks[id as usize].back as usize + 1,
                 None => 0,
             };
 
@@ -628,6 +1283,11 @@ impl<K: Key, V> HopSlotMap<K, V> {
 
     /// Clears the slot map. Keeps the allocated memory for reuse.
     ///
+    /// Every cleared slot has its version bumped, so all keys handed out before
+    /// clearing stay invalid, even after their slots are reused. Afterwards all
+    /// slots form a single vacant block, which is built once rather than by
+    /// merging blocks for every removed element.
+    ///
     /// # Examples
     ///
     /// ```
@@ -636,21 +1296,53 @@ impl<K: Key, V> HopSlotMap<K, V> {
     /// for i in 0..10 {
     ///     sm.insert(i);
     /// }
-    /// assert_eq!(sm.len(), 10);
+    /// let old = sm.insert(10);
+    /// assert_eq!(sm.len(), 11);
     /// sm.clear();
     /// assert_eq!(sm.len(), 0);
+    ///
+    /// sm.insert(42);
+    /// assert_eq!(sm.get(old), None);
     /// ```
     pub fn clear(&mut self) {
         self.drain();
     }
 
-    /// Clears the slot map, returning all key-value pairs in arbitrary order as
-    /// an iterator. Keeps the allocated memory for reuse.
+    /// Clears the slot map and releases its storage, as if by calling
+    /// [`clear`](Self::clear) followed by
+    /// [`shrink_to_fit`](Self::shrink_to_fit).
+    ///
+    /// All keys handed out before stay invalid. Released slots no longer
+    /// remember their versions, but keys to them are out of bounds until the
+    /// slot map grows again, and new slots start past the highest version a
+    /// released slot ever had.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+    /// sm.clear_and_shrink();
+    /// assert!(sm.is_empty());
+    /// assert!(sm.capacity() < 100);
+    ///
+    /// let k = sm.insert(42);
+    /// assert!(keys.iter().all(|&old| sm.get(old).is_none()));
+    /// assert_eq!(sm[k], 42);
+    /// ```
+    pub fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.shrink_to_fit();
+    }
+
+    /// Clears the slot map, returning all key-value pairs in ascending slot
+    /// order as an iterator. Keeps the allocated memory for reuse.
     ///
     /// When the iterator is dropped all elements in the slot map are removed,
     /// even if the iterator was not fully consumed. If the iterator is not
-    /// dropped (using e.g. [`std::mem::forget`]), only the elements that were
-    /// iterated over are removed.
+    /// dropped (using e.g. [`std::mem::forget`]), the slot map is left empty
+    /// and the elements that were not iterated over are leaked.
     ///
     /// # Examples
     ///
@@ -663,9 +1355,76 @@ impl<K: Key, V> HopSlotMap<K, V> {
     /// assert_eq!(v, vec![(k, 0)]);
     /// ```
     pub fn drain(&mut self) -> Drain<K, V> {
+        let cur = self.blocks[0].back as usize + 1;
+        let end = self.slots.len();
+        let num_blocks = self.blocks.len();
+        let num_left = self.len();
+
+        // Until the iterator is dropped the slot map only holds the sentinel
+        // and its block, so leaking the iterator leaves it empty and
+        // consistent. The iterator keeps using the slots and blocks beyond,
+        // which stay allocated and are not touched by anything else. Like in
+        // shrink_to_fit, slots pushed again at those indices must start past
+        // every version they handed out, or a leaked iterator would bring old
+        // keys back.
+        self.slots[0].version = self.slots[1..]
+            .iter()
+            .map(|slot| slot.version.wrapping_add(slot.occupied() as u32).max(2))
+            .fold(self.slots[0].version, u32::max);
+        unsafe {
+            self.slots.set_len(1);
+            self.blocks.set_len(1);
+        }
+        self.blocks[0] = Block {
+            front: 0,
+            back: 0,
+            next: 0,
+            prev: 0,
+        };
+        self.num_elems = 0;
+
         Drain {
-            cur: unsafe { self.slots.get_unchecked(0).u.free.other_end as usize + 1 },
+            cur,
+            end,
+            num_blocks,
+            num_left,
+            sm: self,
+        }
+    }
+
+    /// Creates an iterator which uses `f` to decide if an element should be
+    /// removed. If `f(k, &mut v)` returns true the key-value pair is removed
+    /// from the slot map and yielded, otherwise it stays in place. This method
+    /// invalidates any removed keys.
+    ///
+    /// Elements are only removed as the iterator advances, and the vacant
+    /// blocks are kept intact after every removal. If the iterator is dropped
+    /// before it is fully consumed, the elements it has not visited yet stay
+    /// in the slot map, whether or not `f` would have selected them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let k1 = sm.insert(1);
+    /// let k2 = sm.insert(2);
+    /// let k3 = sm.insert(3);
+    ///
+    /// let odd: Vec<_> = sm.drain_filter(|_, v| *v % 2 == 1).collect();
+    /// assert_eq!(odd, vec![(k1, 1), (k3, 3)]);
+    /// assert_eq!(sm.len(), 1);
+    /// assert_eq!(sm[k2], 2);
+    /// ```
+    pub fn drain_filter<F>(&mut self, f: F) -> DrainFilter<'_, K, V, F>
+    where
+        F: FnMut(K, &mut V) -> bool,
+    {
+        DrainFilter {
+            cur: self.blocks[0].back as usize + 1,
+            num_left: self.len(),
             sm: self,
+            f,
         }
     }
 
@@ -761,6 +1520,87 @@ impl<K: Key, V> HopSlotMap<K, V> {
         &mut self.slots.get_unchecked_mut(key.data().idx as usize).u.value
     }
 
+    /// Returns the key of the value behind the given reference, if that
+    /// reference points to a value stored in this slot map. Returns `None` for
+    /// references to anything else, such as a value in another slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let a = sm.insert(1);
+    /// let b = sm.insert(2);
+    /// let mut to_remove = Vec::new();
+    /// for v in sm.values() {
+    ///     if *v == 2 {
+    ///         to_remove.push(sm.key_of(v).unwrap());
+    ///     }
+    /// }
+    /// assert_eq!(to_remove, vec![b]);
+    /// assert_eq!(sm.key_of(&2), None);
+    /// ```
+    pub fn key_of(&self, value: &V) -> Option<K> {
+        // Values live at the start of the slot union, which need not be the
+        // start of the slot, so measure from the union of the sentinel slot.
+        // The distance is then a multiple of the slot size.
+        let base: *const SlotUnion<V> = &self.slots[0].u;
+        let ptr: *const V = value;
+        let offset = (ptr as usize).wrapping_sub(base as usize);
+        let stride = core::mem::size_of::<Slot<V>>();
+        let idx = offset / stride;
+        if idx * stride != offset {
+            return None;
+        }
+
+        match self.slots.get(idx) {
+            Some(slot) if idx > 0 && slot.occupied() => {
+                Some(KeyData::new(idx as u32, slot.version).into())
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns mutable references to the values corresponding to the two given
+    /// keys. Both keys must be valid and refer to different slots, otherwise
+    /// None is returned.
+    ///
+    /// Unlike [`get_disjoint_mut`](Self::get_disjoint_mut) this does not
+    /// require const generics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let ka = sm.insert(1);
+    /// let kb = sm.insert(2);
+    /// assert_eq!(sm.get2_mut(ka, ka), None); // Not disjoint.
+    /// if let Some((a, b)) = sm.get2_mut(ka, kb) {
+    ///     *a += 10;
+    ///     *b += 20;
+    /// }
+    /// assert_eq!(sm[ka], 11);
+    /// assert_eq!(sm[kb], 22);
+    /// ```
+    pub fn get2_mut(&mut self, a: K, b: K) -> Option<(&mut V, &mut V)> {
+        // Comparing slot indices rather than keys ensures two versions of the
+        // same slot can never alias, even though only one can be valid.
+        if a.data().idx == b.data().idx || !self.contains_key(a) || !self.contains_key(b) {
+            return None;
+        }
+
+        // Derive both references from a single raw pointer so that creating
+        // the second one does not invalidate the first. Both slots are
+        // occupied, so their union holds a value rather than freelist data.
+        unsafe {
+            let slots = self.slots.as_mut_ptr();
+            let va = &mut *(*slots.add(a.data().idx as usize)).u.value;
+            let vb = &mut *(*slots.add(b.data().idx as usize)).u.value;
+            Some((va, vb))
+        }
+    }
+
     /// Returns mutable references to the values corresponding to the given
     /// keys. All keys must be valid and disjoint, otherwise [`None`] is
     /// returned.
@@ -860,7 +1700,7 @@ impl<K: Key, V> HopSlotMap<K, V> {
         core::mem::transmute_copy::<_, [&mut V; N]>(&ptrs)
     }
 
-    /// An iterator visiting all key-value pairs in arbitrary order. The
+    /// An iterator visiting all key-value pairs in ascending slot order. The
     /// iterator element type is `(K, &'a V)`.
     ///
     /// # Examples
@@ -878,14 +1718,16 @@ impl<K: Key, V> HopSlotMap<K, V> {
     /// ```
     pub fn iter(&self) -> Iter<K, V> {
         Iter {
-            cur: unsafe { self.slots.get_unchecked(0).u.free.other_end as usize + 1 },
+            cur: self.blocks[0].back as usize + 1,
+            end: self.slots.len(),
             num_left: self.len(),
             slots: &self.slots[..],
+            blocks: &self.blocks[..],
             _k: PhantomData,
         }
     }
 
-    /// An iterator visiting all key-value pairs in arbitrary order, with
+    /// An iterator visiting all key-value pairs in ascending slot order, with
     /// mutable references to the values. The iterator element type is
     /// `(K, &'a mut V)`.
     ///
@@ -910,15 +1752,17 @@ impl<K: Key, V> HopSlotMap<K, V> {
     /// ```
     pub fn iter_mut(&mut self) -> IterMut<K, V> {
         IterMut {
-            cur: 0,
+            cur: self.blocks[0].back as usize + 1,
+            end: self.slots.len(),
             num_left: self.len(),
             slots: &mut self.slots[..],
+            blocks: &self.blocks[..],
             _k: PhantomData,
         }
     }
 
-    /// An iterator visiting all keys in arbitrary order. The iterator element
-    /// type is `K`.
+    /// An iterator visiting all keys in ascending slot order. The iterator
+    /// element type is `K`.
     ///
     /// # Examples
     ///
@@ -937,8 +1781,8 @@ impl<K: Key, V> HopSlotMap<K, V> {
         Keys { inner: self.iter() }
     }
 
-    /// An iterator visiting all values in arbitrary order. The iterator element
-    /// type is `&'a V`.
+    /// An iterator visiting all values in ascending slot order. The iterator
+    /// element type is `&'a V`.
     ///
     /// # Examples
     ///
@@ -957,8 +1801,8 @@ impl<K: Key, V> HopSlotMap<K, V> {
         Values { inner: self.iter() }
     }
 
-    /// An iterator visiting all values mutably in arbitrary order. The iterator
-    /// element type is `&'a mut V`.
+    /// An iterator visiting all values mutably in ascending slot order. The
+    /// iterator element type is `&'a mut V`.
     ///
     /// # Examples
     ///
@@ -979,6 +1823,211 @@ impl<K: Key, V> HopSlotMap<K, V> {
             inner: self.iter_mut(),
         }
     }
+
+    /// Creates a consuming iterator visiting all keys in ascending slot order.
+    /// The slot map cannot be used after calling this. The iterator element
+    /// type is `K`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let k0 = sm.insert(10);
+    /// let k1 = sm.insert(20);
+    /// let keys: Vec<_> = sm.into_keys().collect();
+    /// assert_eq!(keys, vec![k0, k1]);
+    /// ```
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    /// Creates a consuming iterator visiting all values in ascending slot
+    /// order. The slot map cannot be used after calling this. The iterator
+    /// element type is `V`.
+    ///
+    /// Values that have not been yielded are dropped together with the
+    /// iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// sm.insert(String::from("foo"));
+    /// sm.insert(String::from("bar"));
+    /// let values: Vec<_> = sm.into_values().collect();
+    /// assert_eq!(values, vec!["foo", "bar"]);
+    /// ```
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+
+    /// An iterator over the blocks of contiguous vacant slots, in no particular
+    /// order. The iterator element type is `(u32, u32)`, the index of the
+    /// first slot of a block and the number of slots in it.
+    ///
+    /// Every block is a single hop during iteration, so many small blocks
+    /// indicate fragmentation, see also [`fragmentation`](Self::fragmentation)
+    /// and [`compact`](Self::compact). This takes time linear in the number of
+    /// blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+    /// sm.remove(keys[2]);
+    /// sm.remove(keys[3]);
+    /// sm.remove(keys[7]);
+    ///
+    /// let mut blocks: Vec<_> = sm.vacant_blocks().collect();
+    /// blocks.sort();
+    /// assert_eq!(blocks, vec![(3, 2), (8, 1)]);
+    /// ```
+    pub fn vacant_blocks(&self) -> VacantBlocks<'_> {
+        VacantBlocks {
+            blocks: self.blocks.iter(),
+        }
+    }
+
+    /// Returns the number of blocks of contiguous vacant slots and the number
+    /// of slots in the largest of them, or `(0, 0)` if there are no vacant
+    /// slots. This takes time linear in the number of blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+    /// assert_eq!(sm.fragmentation(), (0, 0));
+    ///
+    /// for k in keys.iter().step_by(2) {
+    ///     sm.remove(*k);
+    /// }
+    /// assert_eq!(sm.fragmentation(), (5, 1));
+    ///
+    /// sm.compact(|_, _, _| {});
+    /// assert_eq!(sm.fragmentation(), (0, 0));
+    /// ```
+    pub fn fragmentation(&self) -> (usize, u32) {
+        self.vacant_blocks()
+            .fold((0, 0), |(n, largest), (_, len)| (n + 1, largest.max(len)))
+    }
+
+    /// Verifies the internal consistency of the slot map, returning the first
+    /// inconsistency found. Besides the checks done by
+    /// [`SlotMap::check_invariants`](crate::SlotMap::check_invariants) this
+    /// verifies that every block of vacant slots on the freelist is maximal
+    /// and that its metadata matches the slots it spans. This takes time
+    /// linear in the number of slots and is intended for testing and fuzzing
+    /// code built on top of the slot map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// let mut sm = HopSlotMap::new();
+    /// let k = sm.insert(1);
+    /// sm.insert(2);
+    /// sm.remove(k);
+    /// assert_eq!(sm.check_invariants(), Ok(()));
+    /// ```
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        if self.slots[0].occupied() {
+            return Err(InvariantError::SentinelOccupied);
+        }
+
+        let vacant = |idx: u32| match self.slots.get(idx as usize) {
+            None => Err(InvariantError::FreeListOutOfBounds { idx }),
+            Some(slot) if slot.occupied() => Err(InvariantError::FreeListOccupied { idx }),
+            Some(slot) => Ok(unsafe { slot.u.block }),
+        };
+
+        // Walk the circular list of blocks, starting at the sentinel's block.
+        let mut in_block = alloc::vec![false; self.slots.len()];
+        let mut linked = alloc::vec![false; self.blocks.len()];
+        let mut id = 0;
+        loop {
+            // Links were checked by the previous block, only the sentinel
+            // block can be missing.
+            let block = match self.blocks.get(id as usize) {
+                Some(block) => *block,
+                None => return Err(InvariantError::InvalidBlock { idx: 0 }),
+            };
+            if linked[id as usize] {
+                return Err(InvariantError::FreeListCycle);
+            }
+            linked[id as usize] = true;
+
+            // The block must span vacant slots only, be maximal, and both of
+            // its ends must refer to it. Only the sentinel block starts at 0.
+            let Block { front, back, .. } = block;
+            let invalid = InvariantError::InvalidBlock { idx: front };
+            vacant(front)?;
+            if back < front || (front == 0) != (id == 0) {
+                return Err(invalid);
+            }
+            if front > 0 && !self.slots[front as usize - 1].occupied() {
+                return Err(invalid);
+            }
+            for i in front..=back {
+                vacant(i).map_err(|_| invalid)?;
+                if in_block[i as usize] {
+                    return Err(InvariantError::FreeListCycle);
+                }
+                in_block[i as usize] = true;
+            }
+            if vacant(front)? != id || vacant(back)? != id {
+                return Err(invalid);
+            }
+            if matches!(self.slots.get(back as usize + 1), Some(s) if !s.occupied()) {
+                return Err(invalid);
+            }
+            if self.blocks.get(block.next as usize).map(|next| next.prev) != Some(id) {
+                return Err(invalid);
+            }
+
+            id = block.next;
+            if id == 0 {
+                break;
+            }
+        }
+
+        let mut occupied = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            if i > 0 && slot.version == 0 {
+                return Err(InvariantError::ZeroVersion { idx: i as u32 });
+            }
+            if slot.occupied() {
+                occupied += 1;
+            } else if !in_block[i] {
+                return Err(InvariantError::UnreachableVacant { idx: i as u32 });
+            }
+        }
+
+        // Every block must be linked.
+        if let Some(i) = linked.iter().position(|&linked| !linked) {
+            return Err(InvariantError::InvalidBlock {
+                idx: self.blocks[i].front,
+            });
+        }
+
+        if occupied != self.len() {
+            return Err(InvariantError::LenMismatch {
+                len: self.len(),
+                occupied,
+            });
+        }
+
+        Ok(())
+    }
 }
 
 impl<K: Key, V> Clone for HopSlotMap<K, V>
@@ -988,13 +2037,22 @@ where
     fn clone(&self) -> Self {
         Self {
             slots: self.slots.clone(),
+            blocks: self.blocks.clone(),
             ..*self
         }
     }
 
     fn clone_from(&mut self, source: &Self) {
         self.slots.clone_from(&source.slots);
+        self.blocks.clone_from(&source.blocks);
         self.num_elems = source.num_elems;
+        self.policy = source.policy;
+    }
+}
+
+impl<K: Key, V: fmt::Debug> fmt::Debug for HopSlotMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
@@ -1004,23 +2062,156 @@ impl<K: Key, V> Default for HopSlotMap<K, V> {
     }
 }
 
+/// Two slot maps are equal if they contain the same key-value pairs. Vacant
+/// slots and the layout of the vacant blocks are not taken into account.
+impl<K: Key, V: PartialEq> PartialEq for HopSlotMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+
+        self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Key, V: Eq> Eq for HopSlotMap<K, V> {}
+
+/// A [`HopSlotMap`] and a [`SlotMap`] are equal if they contain the same
+/// key-value pairs.
+impl<K: Key, V: PartialEq> PartialEq<SlotMap<K, V>> for HopSlotMap<K, V> {
+    fn eq(&self, other: &SlotMap<K, V>) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+
+        self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+/// A [`SlotMap`] and a [`HopSlotMap`] are equal if they contain the same
+/// key-value pairs.
+impl<K: Key, V: PartialEq> PartialEq<HopSlotMap<K, V>> for SlotMap<K, V> {
+    fn eq(&self, other: &HopSlotMap<K, V>) -> bool {
+        other == self
+    }
+}
+
+/// Converts a [`SlotMap`] into a [`HopSlotMap`] without changing any keys.
+///
+/// Every element stays at its key, and keys of removed elements stay invalid,
+/// so keys handed out by the [`SlotMap`] keep working. Slots held by a
+/// [`ReservedKey`](crate::basic::ReservedKey) become vacant, and the key of
+/// the reservation stays invalid. The reuse policy is reset to the default of
+/// [`HopSlotMap`].
+///
+/// This function must iterate over all slots, empty or not.
+///
+/// # Examples
+///
+/// ```
+/// # use slotmap::*;
+/// let mut sm = SlotMap::new();
+/// let foo = sm.insert("foo");
+/// let bar = sm.insert("bar");
+/// sm.remove(foo);
+///
+/// let hsm = HopSlotMap::from(sm);
+/// assert_eq!(hsm[bar], "bar");
+/// assert!(!hsm.contains_key(foo));
+/// ```
+impl<K: Key, V> From<SlotMap<K, V>> for HopSlotMap<K, V> {
+    fn from(sm: SlotMap<K, V>) -> Self {
+        Self::from_slot_parts(sm.into_slot_parts())
+    }
+}
+
+/// Converts a [`HopSlotMap`] into a [`SlotMap`] without changing any keys.
+///
+/// Every element stays at its key, and keys of removed elements stay invalid,
+/// so keys handed out by the [`HopSlotMap`] keep working. The reuse policy is
+/// reset to the default of [`SlotMap`].
+///
+/// This function must iterate over all slots, empty or not.
+///
+/// # Examples
+///
+/// ```
+/// # use slotmap::*;
+/// let mut hsm = HopSlotMap::new();
+/// let foo = hsm.insert("foo");
+/// let bar = hsm.insert("bar");
+/// hsm.remove(foo);
+///
+/// let sm = SlotMap::from(hsm);
+/// assert_eq!(sm[bar], "bar");
+/// assert!(!sm.contains_key(foo));
+/// ```
+impl<K: Key, V> From<HopSlotMap<K, V>> for SlotMap<K, V> {
+    fn from(hsm: HopSlotMap<K, V>) -> Self {
+        Self::from_slot_parts(hsm.into_slot_parts())
+    }
+}
+
+impl<K: Key, V> FromIterator<V> for HopSlotMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut sm = Self::with_capacity_and_key(iter.size_hint().0);
+
+        // A fresh slot map has no vacant blocks besides the sentinel, so we
+        // can append occupied slots without touching the freelist.
+        for value in iter {
+            if sm.is_full() {
+                panic!("HopSlotMap number of elements overflow");
+            }
+            sm.slots.push(Slot {
+                u: SlotUnion {
+                    value: ManuallyDrop::new(value),
+                },
+                version: 1,
+            });
+            sm.num_elems += 1;
+        }
+        sm
+    }
+}
+
+/// Inserts every value, discarding the keys. Vacant slots are reused before
+/// the storage grows, just like with repeated [`insert`](HopSlotMap::insert).
+impl<K: Key, V> Extend<V> for HopSlotMap<K, V> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl<'a, K: Key, V: 'a + Copy> Extend<&'a V> for HopSlotMap<K, V> {
+    fn extend<I: IntoIterator<Item = &'a V>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
 impl<K: Key, V> Index<K> for HopSlotMap<K, V> {
     type Output = V;
 
     fn index(&self, key: K) -> &V {
         match self.get(key) {
             Some(r) => r,
-            None => panic!("invalid HopSlotMap key used"),
+            None => self.invalid_key_panic(key),
         }
     }
 }
 
 impl<K: Key, V> IndexMut<K> for HopSlotMap<K, V> {
     fn index_mut(&mut self, key: K) -> &mut V {
-        match self.get_mut(key) {
-            Some(r) => r,
-            None => panic!("invalid HopSlotMap key used"),
+        if !self.contains_key(key) {
+            self.invalid_key_panic(key);
         }
+
+        // Safe, we just checked that the key is valid.
+        unsafe { self.get_unchecked_mut(key) }
     }
 }
 
@@ -1028,32 +2219,69 @@ impl<K: Key, V> IndexMut<K> for HopSlotMap<K, V> {
 /// A draining iterator for [`HopSlotMap`].
 ///
 /// This iterator is created by [`HopSlotMap::drain`].
-#[derive(Debug)]
 pub struct Drain<'a, K: Key + 'a, V: 'a> {
     cur: usize,
+    end: usize,
+    num_blocks: usize,
+    num_left: usize,
+    sm: &'a mut HopSlotMap<K, V>,
+}
+
+/// A draining iterator for [`HopSlotMap`] that only removes the elements
+/// selected by a predicate.
+///
+/// This iterator is created by [`HopSlotMap::drain_filter`].
+pub struct DrainFilter<'a, K: 'a + Key, V: 'a, F> {
+    cur: usize,
+    num_left: usize,
     sm: &'a mut HopSlotMap<K, V>,
+    f: F,
+}
+
+impl<'a, K: Key, V, F> fmt::Debug for DrainFilter<'a, K, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrainFilter").finish()
+    }
 }
 
 /// An iterator that moves key-value pairs out of a [`HopSlotMap`].
 ///
 /// This iterator is created by calling the `into_iter` method on [`HopSlotMap`],
 /// provided by the [`IntoIterator`] trait.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct IntoIter<K: Key, V> {
     cur: usize,
     num_left: usize,
     slots: Vec<Slot<V>>,
+    blocks: Vec<Block>,
     _k: PhantomData<fn(K) -> K>,
 }
 
+/// An iterator that moves keys out of a [`HopSlotMap`].
+///
+/// This iterator is created by [`HopSlotMap::into_keys`].
+#[derive(Clone)]
+pub struct IntoKeys<K: Key, V> {
+    inner: IntoIter<K, V>,
+}
+
+/// An iterator that moves values out of a [`HopSlotMap`].
+///
+/// This iterator is created by [`HopSlotMap::into_values`].
+#[derive(Clone)]
+pub struct IntoValues<K: Key, V> {
+    inner: IntoIter<K, V>,
+}
+
 /// An iterator over the key-value pairs in a [`HopSlotMap`].
 ///
 /// This iterator is created by [`HopSlotMap::iter`].
-#[derive(Debug)]
 pub struct Iter<'a, K: Key + 'a, V: 'a> {
     cur: usize,
+    end: usize,
     num_left: usize,
     slots: &'a [Slot<V>],
+    blocks: &'a [Block],
     _k: PhantomData<fn(K) -> K>,
 }
 
@@ -1061,8 +2289,10 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Iter<'a, K, V> {
     fn clone(&self) -> Self {
         Iter {
             cur: self.cur,
+            end: self.end,
             num_left: self.num_left,
             slots: self.slots,
+            blocks: self.blocks,
             _k: self._k.clone(),
         }
     }
@@ -1071,18 +2301,18 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Iter<'a, K, V> {
 /// A mutable iterator over the key-value pairs in a [`HopSlotMap`].
 ///
 /// This iterator is created by [`HopSlotMap::iter_mut`].
-#[derive(Debug)]
 pub struct IterMut<'a, K: Key + 'a, V: 'a> {
     cur: usize,
+    end: usize,
     num_left: usize,
     slots: &'a mut [Slot<V>],
+    blocks: &'a [Block],
     _k: PhantomData<fn(K) -> K>,
 }
 
 /// An iterator over the keys in a [`HopSlotMap`].
 ///
 /// This iterator is created by [`HopSlotMap::keys`].
-#[derive(Debug)]
 pub struct Keys<'a, K: Key + 'a, V: 'a> {
     inner: Iter<'a, K, V>,
 }
@@ -1098,7 +2328,6 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Keys<'a, K, V> {
 /// An iterator over the values in a [`HopSlotMap`].
 ///
 /// This iterator is created by [`HopSlotMap::values`].
-#[derive(Debug)]
 pub struct Values<'a, K: Key + 'a, V: 'a> {
     inner: Iter<'a, K, V>,
 }
@@ -1114,68 +2343,219 @@ impl<'a, K: 'a + Key, V: 'a> Clone for Values<'a, K, V> {
 /// A mutable iterator over the values in a [`HopSlotMap`].
 ///
 /// This iterator is created by [`HopSlotMap::values_mut`].
-#[derive(Debug)]
 pub struct ValuesMut<'a, K: Key + 'a, V: 'a> {
     inner: IterMut<'a, K, V>,
 }
 
-impl<'a, K: Key, V> Iterator for Drain<'a, K, V> {
-    type Item = (K, V);
-
-    fn next(&mut self) -> Option<(K, V)> {
-        // All unchecked indices are safe due to the invariants of the freelist
-        // and that self.sm.len() guarantees there is another element.
-        if self.sm.len() == 0 {
-            return None;
-        }
+/// An iterator over the blocks of vacant slots in a [`HopSlotMap`].
+///
+/// This iterator is created by [`HopSlotMap::vacant_blocks`].
+#[derive(Clone, Debug)]
+pub struct VacantBlocks<'a> {
+    blocks: core::slice::Iter<'a, Block>,
+}
 
-        // Skip ahead to next element. Must do this before removing.
-        let idx = self.cur;
-        self.cur = match self.sm.slots.get(idx + 1).map(|s| s.get()) {
-            Some(Occupied(_)) => idx + 1,
-            Some(Vacant(free)) => free.other_end as usize + 1,
-            None => 0,
+impl<'a, K: Key, V: fmt::Debug> fmt::Debug for Drain<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // All remaining elements are at or after cur, and the blocks past cur
+        // are still intact.
+        let remaining: Iter<K, V> = Iter {
+            cur: self.cur,
+            end: self.end,
+            num_left: self.num_left,
+            slots: unsafe { core::slice::from_raw_parts(self.sm.slots.as_ptr(), self.end) },
+            blocks: unsafe { core::slice::from_raw_parts(self.sm.blocks.as_ptr(), self.num_blocks) },
+            _k: PhantomData,
         };
+        f.debug_list().entries(remaining).finish()
+    }
+}
 
-        let key = KeyData::new(idx as u32, unsafe { self.sm.slots.get_unchecked(idx).version });
-        Some((key.into(), unsafe { self.sm.remove_from_slot(idx) }))
+impl<K: Key, V> fmt::Debug for IntoIter<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("remaining", &self.num_left)
+            .finish()
     }
+}
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.sm.len(), Some(self.sm.len()))
+impl<K: Key, V> fmt::Debug for IntoKeys<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoKeys")
+            .field("remaining", &self.inner.num_left)
+            .finish()
     }
 }
 
-impl<'a, K: Key, V> Drop for Drain<'a, K, V> {
-    fn drop(&mut self) {
-        self.for_each(|_drop| {});
+impl<K: Key, V> fmt::Debug for IntoValues<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoValues")
+            .field("remaining", &self.inner.num_left)
+            .finish()
     }
 }
 
-impl<K: Key, V> Iterator for IntoIter<K, V> {
-    type Item = (K, V);
+impl<'a, K: Key, V: fmt::Debug> fmt::Debug for Iter<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
 
-    fn next(&mut self) -> Option<(K, V)> {
-        if self.cur >= self.slots.len() {
-            return None;
-        }
+impl<'a, K: Key, V> fmt::Debug for IterMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IterMut")
+            .field("remaining", &self.num_left)
+            .finish()
+    }
+}
 
-        let idx = match self.slots[self.cur].get() {
-            Occupied(_) => self.cur,
-            Vacant(free) => {
-                // Skip block of contiguous vacant slots.
-                let idx = free.other_end as usize + 1;
-                if idx >= self.slots.len() {
-                    return None;
-                }
-                idx
-            },
-        };
+impl<'a, K: Key, V> fmt::Debug for Keys<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
 
-        self.cur = idx + 1;
-        self.num_left -= 1;
-        let slot = &mut self.slots[idx];
-        let key = KeyData::new(idx as u32, slot.version).into();
+impl<'a, K: Key, V: fmt::Debug> fmt::Debug for Values<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a, K: Key, V> fmt::Debug for ValuesMut<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ValuesMut")
+            .field("remaining", &self.inner.num_left)
+            .finish()
+    }
+}
+
+impl<'a, K: Key, V> Iterator for Drain<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        if self.num_left == 0 {
+            return None;
+        }
+        self.num_left -= 1;
+
+        // This is safe because cur is occupied, and the slots and blocks past
+        // it are untouched since the iterator was created. The freelist is
+        // only rebuilt when the iterator is dropped.
+        unsafe {
+            let slots = self.sm.slots.as_mut_ptr();
+            let idx = self.cur;
+            self.cur = if idx + 1 == self.end {
+                self.end
+            } else {
+                match (*slots.add(idx + 1)).get() {
+                    Occupied(_) => idx + 1,
+                    Vacant(&id) => (*self.sm.blocks.as_ptr().add(id as usize)).back as usize + 1,
+                }
+            };
+
+            let slot = &mut *slots.add(idx);
+            let key = KeyData::new(idx as u32, slot.version);
+            slot.version = slot.version.wrapping_add(1).max(2);
+            let value = ManuallyDrop::take(&mut slot.u.value);
+            slot.u.block = 0;
+            Some((key.into(), value))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.num_left, Some(self.num_left))
+    }
+}
+
+impl<'a, K: Key, V> Drop for Drain<'a, K, V> {
+    fn drop(&mut self) {
+        // Give the slots back, then vacate all remaining slots and rebuild the
+        // freelist once, even if dropping a value panics.
+        struct Rebuild<'b, K: Key, V>(&'b mut HopSlotMap<K, V>);
+        impl<'b, K: Key, V> Drop for Rebuild<'b, K, V> {
+            fn drop(&mut self) {
+                self.0.rebuild_freelist();
+            }
+        }
+
+        unsafe { self.sm.slots.set_len(self.end) };
+        let guard = Rebuild(self.sm);
+        let mut num_left = self.num_left;
+        let mut idx = self.cur;
+        while num_left > 0 {
+            // This is safe because all remaining elements are at or after cur,
+            // and num_left guarantees there is another occupied slot.
+            let slot = unsafe { guard.0.slots.get_unchecked_mut(idx) };
+            if slot.occupied() {
+                num_left -= 1;
+                // Vacate the slot before dropping its value, so a panicking
+                // drop can't cause a double drop.
+                slot.version = slot.version.wrapping_add(1).max(2);
+                unsafe {
+                    ManuallyDrop::drop(&mut slot.u.value);
+                    slot.u.block = 0;
+                }
+            }
+            idx += 1;
+        }
+    }
+}
+
+impl<'a, K: Key, V, F> Iterator for DrainFilter<'a, K, V, F>
+where
+    F: FnMut(K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        // All unchecked indices are safe due to the invariants of the freelist
+        // and that num_left guarantees cur points to an occupied slot.
+        while self.num_left > 0 {
+            self.num_left -= 1;
+
+            // Skip ahead to the next element. Must do this before removing,
+            // which may merge the vacant block after idx into a larger one.
+            let idx = self.cur;
+            self.cur = match self.sm.slots.get(idx + 1).map(|s| s.get()) {
+                Some(Occupied(_)) => idx + 1,
+                Some(Vacant(&id)) => self.sm.blocks[id as usize].back as usize + 1,
+                None => 0,
+            };
+
+            let slot = unsafe { self.sm.slots.get_unchecked_mut(idx) };
+            let key = KeyData::new(idx as u32, slot.version).into();
+            if (self.f)(key, unsafe { &mut *slot.u.value }) {
+                return Some((key, unsafe { self.sm.remove_from_slot(idx) }));
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.num_left))
+    }
+}
+
+impl<K: Key, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        if self.cur >= self.slots.len() {
+            return None;
+        }
+
+        let mut idx = self.cur;
+        let mut slot = &mut self.slots[idx];
+        if let Vacant(&id) = slot.get() {
+            // Skip block of contiguous vacant slots.
+            idx = self.blocks[id as usize].back as usize + 1;
+            slot = self.slots.get_mut(idx)?;
+        }
+
+        self.cur = idx + 1;
+        self.num_left -= 1;
+        let key = KeyData::new(idx as u32, slot.version).into();
         slot.version = 0; // Prevent dropping after extracting the value.
         Some((key, unsafe { ManuallyDrop::take(&mut slot.u.value) }))
     }
@@ -1185,6 +2565,30 @@ impl<K: Key, V> Iterator for IntoIter<K, V> {
     }
 }
 
+impl<K: Key, V> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K: Key, V> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
 impl<'a, K: Key, V> Iterator for Iter<'a, K, V> {
     type Item = (K, &'a V);
 
@@ -1196,13 +2600,14 @@ impl<'a, K: Key, V> Iterator for Iter<'a, K, V> {
         }
         self.num_left -= 1;
 
-        let idx = match unsafe { self.slots.get_unchecked(self.cur).get() } {
-            Occupied(_) => self.cur,
-            Vacant(free) => free.other_end as usize + 1,
-        };
+        let mut idx = self.cur;
+        let mut slot = unsafe { self.slots.get_unchecked(idx) };
+        if let Vacant(&id) = slot.get() {
+            idx = unsafe { self.blocks.get_unchecked(id as usize).back as usize + 1 };
+            slot = unsafe { self.slots.get_unchecked(idx) };
+        }
 
         self.cur = idx + 1;
-        let slot = unsafe { self.slots.get_unchecked(idx) };
         let key = KeyData::new(idx as u32, slot.version).into();
         Some((key, unsafe { &*slot.u.value }))
     }
@@ -1216,28 +2621,24 @@ impl<'a, K: Key, V> Iterator for IterMut<'a, K, V> {
     type Item = (K, &'a mut V);
 
     fn next(&mut self) -> Option<(K, &'a mut V)> {
-        if self.cur >= self.slots.len() {
+        // All unchecked indices are safe due to the invariants of the freelist
+        // and that num_left guarantees there is another element.
+        if self.num_left == 0 {
             return None;
         }
+        self.num_left -= 1;
 
-        let idx = match self.slots[self.cur].get() {
-            Occupied(_) => self.cur,
-            Vacant(free) => {
-                // Skip block of contiguous vacant slots.
-                let idx = free.other_end as usize + 1;
-                if idx >= self.slots.len() {
-                    return None;
-                }
-                idx
-            },
-        };
+        let mut idx = self.cur;
+        let mut slot = unsafe { self.slots.get_unchecked_mut(idx) };
+        if let Vacant(&id) = slot.get() {
+            idx = unsafe { self.blocks.get_unchecked(id as usize).back as usize + 1 };
+            slot = unsafe { self.slots.get_unchecked_mut(idx) };
+        }
 
         self.cur = idx + 1;
-        self.num_left -= 1;
 
         // Unsafe necessary because Rust can't deduce that we won't
         // return multiple references to the same value.
-        let slot = &mut self.slots[idx];
         let version = slot.version;
         let value_ref = unsafe {
             let ptr: *mut V = &mut *slot.u.value;
@@ -1287,6 +2688,98 @@ impl<'a, K: Key, V> Iterator for ValuesMut<'a, K, V> {
     }
 }
 
+impl<'a> Iterator for VacantBlocks<'a> {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<(u32, u32)> {
+        // The sentinel block starts at the sentinel, which is not a vacant
+        // slot, and it may not contain any other slot.
+        self.blocks.by_ref().find_map(|block| {
+            let front = block.front.max(1);
+            if block.back < front {
+                return None;
+            }
+            Some((front, block.back - front + 1))
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.blocks.len();
+        (len.saturating_sub(1), Some(len))
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, &'a V)> {
+        // All unchecked indices are safe due to the invariants of the freelist
+        // and that num_left guarantees there is another element before end.
+        if self.num_left == 0 {
+            return None;
+        }
+        self.num_left -= 1;
+
+        // The slot before end is either occupied or the back of a vacant
+        // block, which refers to its block.
+        let mut idx = self.end - 1;
+        let mut slot = unsafe { self.slots.get_unchecked(idx) };
+        if let Vacant(&id) = slot.get() {
+            idx = unsafe { self.blocks.get_unchecked(id as usize).front as usize - 1 };
+            slot = unsafe { self.slots.get_unchecked(idx) };
+        }
+
+        self.end = idx;
+        let key = KeyData::new(idx as u32, slot.version).into();
+        Some((key, unsafe { &*slot.u.value }))
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, &'a mut V)> {
+        // All unchecked indices are safe due to the invariants of the freelist
+        // and that num_left guarantees there is another element before end.
+        if self.num_left == 0 {
+            return None;
+        }
+        self.num_left -= 1;
+
+        let mut idx = self.end - 1;
+        let mut slot = unsafe { self.slots.get_unchecked_mut(idx) };
+        if let Vacant(&id) = slot.get() {
+            idx = unsafe { self.blocks.get_unchecked(id as usize).front as usize - 1 };
+            slot = unsafe { self.slots.get_unchecked_mut(idx) };
+        }
+
+        self.end = idx;
+
+        // Unsafe necessary because Rust can't deduce that we won't
+        // return multiple references to the same value.
+        let version = slot.version;
+        let value_ref = unsafe {
+            let ptr: *mut V = &mut *slot.u.value;
+            &mut *ptr
+        };
+        Some((KeyData::new(idx as u32, version).into(), value_ref))
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<K> {
+        self.inner.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for Values<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K: Key, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
 impl<'a, K: Key, V> IntoIterator for &'a HopSlotMap<K, V> {
     type Item = (K, &'a V);
     type IntoIter = Iter<'a, K, V>;
@@ -1314,6 +2807,7 @@ impl<K: Key, V> IntoIterator for HopSlotMap<K, V> {
             cur: 0,
             num_left: self.len(),
             slots: self.slots,
+            blocks: self.blocks,
             _k: PhantomData,
         }
     }
@@ -1325,7 +2819,11 @@ impl<'a, K: Key, V> FusedIterator for Keys<'a, K, V> {}
 impl<'a, K: Key, V> FusedIterator for Values<'a, K, V> {}
 impl<'a, K: Key, V> FusedIterator for ValuesMut<'a, K, V> {}
 impl<'a, K: Key, V> FusedIterator for Drain<'a, K, V> {}
+impl<'a, K: Key, V, F: FnMut(K, &mut V) -> bool> FusedIterator for DrainFilter<'a, K, V, F> {}
 impl<K: Key, V> FusedIterator for IntoIter<K, V> {}
+impl<K: Key, V> FusedIterator for IntoKeys<K, V> {}
+impl<K: Key, V> FusedIterator for IntoValues<K, V> {}
+impl<'a> FusedIterator for VacantBlocks<'a> {}
 
 impl<'a, K: Key, V> ExactSizeIterator for Iter<'a, K, V> {}
 impl<'a, K: Key, V> ExactSizeIterator for IterMut<'a, K, V> {}
@@ -1334,6 +2832,8 @@ impl<'a, K: Key, V> ExactSizeIterator for Values<'a, K, V> {}
 impl<'a, K: Key, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
 impl<'a, K: Key, V> ExactSizeIterator for Drain<'a, K, V> {}
 impl<K: Key, V> ExactSizeIterator for IntoIter<K, V> {}
+impl<K: Key, V> ExactSizeIterator for IntoKeys<K, V> {}
+impl<K: Key, V> ExactSizeIterator for IntoValues<K, V> {}
 
 // Serialization with serde.
 #[cfg(feature = "serde")]
@@ -1383,13 +2883,7 @@ mod serialize {
                     Some(value) => SlotUnion {
                         value: ManuallyDrop::new(value),
                     },
-                    None => SlotUnion {
-                        free: FreeListEntry {
-                            next: 0,
-                            prev: 0,
-                            other_end: 0,
-                        },
-                    },
+                    None => SlotUnion { block: 0 },
                 },
                 version: serde_slot.version,
             })
@@ -1410,7 +2904,7 @@ mod serialize {
         where
             D: Deserializer<'de>,
         {
-            let mut slots: Vec<Slot<V>> = Deserialize::deserialize(deserializer)?;
+            let slots: Vec<Slot<V>> = Deserialize::deserialize(deserializer)?;
             if slots.len() >= u32::max_value() as usize {
                 return Err(de::Error::custom(&"too many slots"));
             }
@@ -1420,49 +2914,19 @@ mod serialize {
                 return Err(de::Error::custom(&"first slot not empty"));
             }
 
-            slots[0].u.free = FreeListEntry {
-                next: 0,
-                prev: 0,
-                other_end: 0,
-            };
-
-            // We have our slots, rebuild freelist.
-            let mut num_elems = 0;
-            let mut prev = 0;
-            let mut i = 0;
-            while i < slots.len() {
-                // i is the start of a contiguous block of vacant slots.
-                let front = i;
-                while i < slots.len() && !slots[i].occupied() {
-                    i += 1;
-                }
-                let back = i - 1;
-
-                // Update freelist.
-                unsafe {
-                    slots[back].u.free.other_end = front as u32;
-                    slots[prev].u.free.next = front as u32;
-                    slots[front].u.free = FreeListEntry {
-                        next: 0,
-                        prev: prev as u32,
-                        other_end: back as u32,
-                    };
-                }
-
-                prev = front;
-
-                // Skip occupied slots.
-                while i < slots.len() && slots[i].occupied() {
-                    num_elems += 1;
-                    i += 1;
-                }
+            if slots.iter().skip(1).any(|slot| slot.version == 0) {
+                return Err(de::Error::custom(&"version 0 outside the sentinel"));
             }
 
-            Ok(Self {
-                num_elems,
+            let mut sm = Self {
                 slots,
+                blocks: Vec::new(),
+                num_elems: 0,
+                policy: ReusePolicy::Fifo,
                 _k: PhantomData,
-            })
+            };
+            sm.rebuild_freelist();
+            Ok(sm)
         }
     }
 }
@@ -1628,6 +3092,10 @@ mod tests {
 
                     _ => unreachable!(),
                 }
+
+                if sm.check_invariants().is_err() {
+                    return false;
+                }
             }
 
             let mut smv: Vec<_> = sm.values().collect();
@@ -1636,49 +3104,1453 @@ mod tests {
             hmv.sort();
             smv == hmv
         }
+
+        fn qc_model_every_step(operations: Vec<(u8, u32)>) -> bool {
+            // The model is keyed by the keys the slot map hands out, so every
+            // key ever returned can be checked, including removed ones.
+            let mut model = HashMap::new();
+            let mut keys = Vec::new();
+            let mut removed = HashSet::new();
+            let mut sm = HopSlotMap::new();
+
+            for (op, val) in operations {
+                match op % 5 {
+                    // Insert.
+                    0 => {
+                        let k = sm.insert(val);
+                        if model.insert(k, val).is_some() {
+                            return false;
+                        }
+                        keys.push(k);
+                    }
+
+                    // Insert with key, the value sees the final key.
+                    1 => {
+                        let k = sm.insert_with_key(|k| k.data().idx ^ val);
+                        if sm[k] != k.data().idx ^ val || model.insert(k, sm[k]).is_some() {
+                            return false;
+                        }
+                        keys.push(k);
+                    }
+
+                    // Remove any key handed out so far, stale ones included.
+                    2 => {
+                        if keys.is_empty() { continue; }
+                        let k = keys[val as usize % keys.len()];
+                        if sm.remove(k) != model.remove(&k) {
+                            return false;
+                        }
+                        removed.insert(k);
+                    }
+
+                    // Access.
+                    3 => {
+                        if keys.is_empty() { continue; }
+                        let k = keys[val as usize % keys.len()];
+                        if sm.get(k) != model.get(&k) || sm.contains_key(k) != model.contains_key(&k) {
+                            return false;
+                        }
+                        if let Some(v) = sm.get_mut(k) {
+                            *v = v.wrapping_add(val);
+                            *model.get_mut(&k).unwrap() = *v;
+                        }
+                    }
+
+                    // Retain, removing runs of neighbouring slots at once.
+                    4 => {
+                        sm.retain(|_, v| *v % 3 != val % 3);
+                        model.retain(|k, v| {
+                            let keep = *v % 3 != val % 3;
+                            if !keep {
+                                removed.insert(*k);
+                            }
+                            keep
+                        });
+                    }
+
+                    _ => unreachable!(),
+                }
+
+                // Contents, length and iteration in both directions agree.
+                let forward: Vec<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+                let mut backward: Vec<_> = sm.iter().rev().map(|(k, v)| (k, *v)).collect();
+                backward.reverse();
+                let contents: HashMap<_, _> = forward.iter().copied().collect();
+                if sm.len() != model.len() || forward != backward || contents != model {
+                    return false;
+                }
+                if !forward.windows(2).all(|w| w[0].0.data().idx < w[1].0.data().idx) {
+                    return false;
+                }
+
+                // Removed keys stay invalid, even once their slot is reused.
+                if removed.iter().any(|k| sm.contains_key(*k)) {
+                    return false;
+                }
+
+                if sm.check_invariants().is_err() {
+                    return false;
+                }
+            }
+
+            true
+        }
     }
 
-    #[cfg(feature = "serde")]
     #[test]
-    fn slotmap_serde() {
+    fn check_invariants_detects_corruption() {
         let mut sm = HopSlotMap::new();
-        // Self-referential structure.
-        let first = sm.insert_with_key(|k| (k, 23i32));
-        let second = sm.insert((first, 42));
-
-        // Make some empty slots.
-        let empties = vec![sm.insert((first, 0)), sm.insert((first, 0))];
-        empties.iter().for_each(|k| {
-            sm.remove(*k);
-        });
+        let keys: Vec<_> = (0..8).map(|i| sm.insert(i)).collect();
+        for i in [2, 3, 6].iter() {
+            sm.remove(keys[*i]);
+        }
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        let mut bad = sm.clone();
+        bad.num_elems -= 1;
+        assert_eq!(
+            bad.check_invariants(),
+            Err(InvariantError::LenMismatch { len: 4, occupied: 5 })
+        );
+
+        // Block 3..=4 claims to end at slot 5, which is occupied.
+        let mut bad = sm.clone();
+        bad.blocks[1].back = 5;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::InvalidBlock { idx: 3 }));
+
+        // The back of block 3..=4 refers to the block starting at slot 7.
+        let mut bad = sm.clone();
+        bad.slots[4].u.block = 2;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::InvalidBlock { idx: 3 }));
+
+        // Skip the block starting at slot 7 in the list.
+        let mut bad = sm.clone();
+        bad.blocks[1].next = 0;
+        bad.blocks[0].prev = 1;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::UnreachableVacant { idx: 7 }));
+
+        let mut bad = sm.clone();
+        bad.blocks[2].next = 1;
+        assert!(bad.check_invariants().is_err());
+
+        let mut bad = sm.clone();
+        bad.slots[3].version = 0;
+        assert_eq!(bad.check_invariants(), Err(InvariantError::ZeroVersion { idx: 3 }));
+    }
 
-        let third = sm.insert((second, 0));
-        sm[first].0 = third;
+    #[test]
+    fn version_wraps_past_sentinel_version() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert(1);
+        sm.slots[1].version = u32::MAX;
+        sm.remove(KeyData::new(1, u32::MAX).into());
+        assert_eq!(sm.slots[1].version, 2);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k2 = sm.insert(2);
+        assert_ne!(k, k2);
+        assert_eq!(k2.data().version.get(), 3);
+    }
 
-        let ser = serde_json::to_string(&sm).unwrap();
-        let de: HopSlotMap<DefaultKey, (DefaultKey, i32)> = serde_json::from_str(&ser).unwrap();
-        assert_eq!(de.len(), sm.len());
+    #[test]
+    fn index_mut() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert(1);
+        sm[k] = 5;
+        sm[k] += 1;
+        assert_eq!(sm[k], 6);
+    }
 
-        let mut smkv: Vec<_> = sm.iter().collect();
-        let mut dekv: Vec<_> = de.iter().collect();
-        smkv.sort();
-        dekv.sort();
-        assert_eq!(smkv, dekv);
+    #[test]
+    fn retain_merges_blocks() {
+        let patterns: [fn(usize) -> bool; 5] = [
+            |i| i % 2 == 0,
+            |i| i % 2 == 1,
+            |i| i >= 6,
+            |i| i < 6,
+            |i| i % 5 == 0,
+        ];
+
+        for keep in patterns.iter() {
+            let mut sm = HopSlotMap::new();
+            let keys: Vec<_> = (0..12).map(|i| sm.insert(i)).collect();
+            // Leave a vacant block in the middle that neighbouring removals
+            // merge with.
+            sm.remove(keys[4]);
+            sm.remove(keys[5]);
+
+            sm.retain(|_, v| keep(*v));
+            assert_eq!(sm.check_invariants(), Ok(()));
+
+            let survivors: Vec<_> = (0..12)
+                .filter(|&i| i != 4 && i != 5 && keep(i))
+                .map(|i| (keys[i], i))
+                .collect();
+            let visited: Vec<_> = sm.iter().map(|(k, v)| (k, *v)).collect();
+            assert_eq!(visited, survivors);
+
+            // Refilling the vacant blocks must keep the bookkeeping sound.
+            for i in 0..6 {
+                sm.insert(100 + i);
+            }
+            assert_eq!(sm.check_invariants(), Ok(()));
+            assert_eq!(sm.len(), survivors.len() + 6);
+        }
     }
 
-    #[cfg(feature = "serde")]
     #[test]
-    fn slotmap_serde_freelist() {
+    fn drain_dropped_early() {
         let mut sm = HopSlotMap::new();
-        let k = sm.insert(5i32);
-        sm.remove(k);
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[3]);
+        sm.remove(keys[4]);
+        sm.remove(keys[8]);
+
+        let mut drain = sm.drain();
+        assert_eq!(drain.next(), Some((keys[0], 0)));
+        assert_eq!(drain.next(), Some((keys[1], 1)));
+        drop(drain);
+        assert!(sm.is_empty());
+        assert_eq!(sm.iter().next(), None);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert!(keys.iter().all(|&k| !sm.contains_key(k)));
+
+        // The whole map is one vacant block again.
+        let new_keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.slots.len(), 11);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert!(new_keys.iter().all(|k| !keys.contains(k)));
+        assert_eq!(sm.len(), 10);
+    }
 
-        let ser = serde_json::to_string(&sm).unwrap();
-        let mut de: HopSlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+    #[test]
+    fn drain_leaked() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10usize).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[3]);
+
+        let mut drain = sm.drain();
+        assert_eq!(drain.next(), Some((keys[0], 0)));
+        let rest: Vec<_> = (1..10).filter(|&i| i != 3).map(|i| (keys[i], i)).collect();
+        assert_eq!(format!("{:?}", drain), format!("{:?}", rest));
+        core::mem::forget(drain);
+
+        // The remaining elements are leaked, the slot map is left empty.
+        assert!(sm.is_empty());
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k = sm.insert(42);
+        assert_eq!(sm[k], 42);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // Slots created again don't bring back the keys they had before.
+        let new_keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for old in &keys {
+            assert!(!sm.contains_key(*old));
+            assert!(!new_keys.contains(old) && *old != k);
+        }
+    }
 
-        de.insert(0);
-        de.insert(1);
+    #[test]
+    fn insert_with_key_reuses_block() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..5).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[1]);
+        sm.remove(keys[2]);
+
+        // The key passed in is the one returned, with the reused slot's
+        // version.
+        let mut offered = None;
+        let k = sm.insert_with_key(|k| {
+            offered = Some(k);
+            10
+        });
+        assert_eq!(offered, Some(k));
+        assert!(k != keys[1] && k != keys[2]);
+        assert_eq!(sm[k], 10);
+
+        // A panicking closure doesn't split the vacant block.
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sm.insert_with_key(|_| panic!("oops"));
+        }));
+        assert!(r.is_err());
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.len(), 4);
+        sm.insert(11);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.slots.len(), 6);
+    }
+
+    #[test]
+    fn keys_values_fragmented() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+        for (i, &k) in keys.iter().enumerate() {
+            if i % 7 < 4 {
+                sm.remove(k);
+            }
+        }
+        let survivors: Vec<_> = (0..20).filter(|i| i % 7 >= 4).collect();
+
+        let mut ks = sm.keys();
+        let mut vs = sm.values();
+        for n in (0..=survivors.len()).rev() {
+            assert_eq!(ks.size_hint(), (n, Some(n)));
+            assert_eq!(vs.size_hint(), (n, Some(n)));
+            ks.next();
+            vs.next();
+        }
+        assert!(sm.keys().eq(survivors.iter().map(|&i| keys[i])));
+        assert!(sm.values().eq(survivors.iter()));
+
+        let vm = sm.values_mut();
+        assert_eq!(vm.len(), survivors.len());
+        vm.for_each(|v| *v *= 2);
+        assert!(sm.values().eq(survivors.iter().map(|i| i * 2).collect::<Vec<_>>().iter()));
+    }
+
+    #[test]
+    fn from_iter() {
+        let sm: HopSlotMap<DefaultKey, i32> = core::iter::empty().collect();
+        assert!(sm.is_empty());
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        let sm: HopSlotMap<DefaultKey, i32> = core::iter::once(7).collect();
+        assert_eq!(sm.values().collect::<Vec<_>>(), vec![&7]);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        let mut sm: HopSlotMap<DefaultKey, i32> = (0..100).collect();
+        assert_eq!(sm.len(), 100);
+        assert!(sm.capacity() >= 100);
+        assert!(sm.values().copied().eq(0..100));
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // Collected maps behave like ones built by insert.
+        let keys: Vec<_> = sm.keys().collect();
+        sm.remove(keys[50]);
+        let k = sm.insert(-1);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(k.data().idx, keys[50].data().idx);
+        assert_eq!(sm[k], -1);
+    }
+
+    #[test]
+    fn into_iter_drops_once() {
+        use std::rc::Rc;
+
+        let tracker = Rc::new(());
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|_| sm.insert(tracker.clone())).collect();
+        for &k in &keys[2..5] {
+            sm.remove(k);
+        }
+        assert_eq!(Rc::strong_count(&tracker), 8);
+
+        let mut iter = sm.into_iter();
+        assert_eq!(iter.size_hint(), (7, Some(7)));
+        let (k0, v0) = iter.next().unwrap();
+        let (k1, _) = iter.next().unwrap();
+        let (k5, _) = iter.next().unwrap();
+        assert_eq!((k0, k1, k5), (keys[0], keys[1], keys[5]));
+        assert_eq!(iter.size_hint(), (4, Some(4)));
+        assert_eq!(Rc::strong_count(&tracker), 6);
+
+        // Dropping the iterator early drops the remaining values exactly once.
+        drop(iter);
+        assert_eq!(Rc::strong_count(&tracker), 2);
+        drop(v0);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn clone_preserves_layout() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..50).map(|i| sm.insert(i)).collect();
+        for (i, &k) in keys.iter().enumerate() {
+            if i % 5 != 0 && i % 3 != 0 {
+                sm.remove(k);
+            }
+        }
+
+        let mut clone = sm.clone();
+        assert_eq!(clone.check_invariants(), Ok(()));
+        assert!(sm.iter().eq(clone.iter()));
+        assert!(keys.iter().all(|&k| sm.get(k) == clone.get(k)));
+
+        // Both maps stay in lockstep, including which slots get reused.
+        for i in 0..40 {
+            assert_eq!(sm.insert(100 + i), clone.insert(100 + i));
+        }
+        assert!(sm.iter().eq(clone.iter()));
+
+        let mut target = HopSlotMap::new();
+        target.insert(-1);
+        target.clone_from(&sm);
+        assert_eq!(target.check_invariants(), Ok(()));
+        assert_eq!(target.insert(0), sm.insert(0));
+    }
+
+    #[test]
+    fn eq_ignores_layout() {
+        let mut a = HopSlotMap::new();
+        let mut b = HopSlotMap::new();
+        let mut sm = SlotMap::new();
+        let ka: Vec<_> = (0..6).map(|i| a.insert(i)).collect();
+        let kb: Vec<_> = (0..6).map(|i| b.insert(i)).collect();
+        let ks: Vec<_> = (0..6).map(|i| sm.insert(i)).collect();
+        assert_eq!(ka, kb);
+        assert_eq!(ka, ks);
+        assert_eq!(a, b);
+        assert_eq!(a, sm);
+        assert_eq!(sm, a);
+
+        a.remove(ka[2]);
+        assert_ne!(a, b);
+        assert_ne!(a, sm);
+        b.remove(kb[2]);
+        sm.remove(ks[2]);
+        assert_eq!(a, b);
+        assert_eq!(sm, a);
+
+        // Fragmenting the vacant blocks differently doesn't matter.
+        let tmp = b.insert(10);
+        b.remove(tmp);
+        b.remove(kb[3]);
+        a.remove(ka[3]);
+        assert_eq!(a, b);
+
+        a[ka[0]] = 20;
+        assert_ne!(a, b);
+    }
+
+    quickcheck! {
+        fn qc_double_ended_equiv_vec(values: Vec<u32>, removals: Vec<usize>, ends: Vec<bool>) -> bool {
+            let mut sm = HopSlotMap::new();
+            let keys: Vec<_> = values.iter().map(|v| sm.insert(*v)).collect();
+            for r in removals {
+                if !keys.is_empty() {
+                    sm.remove(keys[r % keys.len()]);
+                }
+            }
+
+            // The model is every live key in slot order, independent of how
+            // iteration hops over vacant blocks.
+            let model: std::collections::VecDeque<_> = keys
+                .iter()
+                .filter_map(|&k| sm.get(k).map(|v| (k, *v)))
+                .collect();
+            // repeat_n needs a newer Rust than the minimum supported one.
+            #[allow(clippy::manual_repeat_n)]
+            let steps = || ends.iter().copied().chain(core::iter::repeat(false).take(model.len() + 1));
+
+            let mut expected = model.clone();
+            let mut iter = sm.iter();
+            for back in steps() {
+                let (e, actual) = if back {
+                    (expected.pop_back(), iter.next_back())
+                } else {
+                    (expected.pop_front(), iter.next())
+                };
+                if actual.map(|(k, v)| (k, *v)) != e || iter.len() != expected.len() {
+                    return false;
+                }
+            }
+
+            let mut expected = model.clone();
+            let mut iter = sm.iter_mut();
+            for back in steps() {
+                let (e, actual) = if back {
+                    (expected.pop_back(), iter.next_back())
+                } else {
+                    (expected.pop_front(), iter.next())
+                };
+                if actual.map(|(k, v)| (k, *v)) != e || iter.len() != expected.len() {
+                    return false;
+                }
+            }
+
+            sm.keys().rev().eq(model.iter().rev().map(|&(k, _)| k))
+                && sm.values().rev().eq(model.iter().rev().map(|(_, v)| v))
+                && sm.values_mut().rev().map(|v| *v).eq(model.iter().rev().map(|&(_, v)| v))
+        }
+    }
+
+    quickcheck! {
+        fn qc_eq_equiv_slotmap(setup: Vec<(u8, u32)>, operations: Vec<(u8, u32)>) -> bool {
+            let mut sm = SlotMap::new();
+            let mut keys = Vec::new();
+            for &(op, val) in &setup {
+                if op % 3 == 0 && !keys.is_empty() {
+                    sm.remove(keys[val as usize % keys.len()]);
+                } else {
+                    keys.push(sm.insert(val));
+                }
+            }
+
+            // Both start with the same keys, but their slots are reused
+            // differently, so inserting makes them drift apart.
+            let mut hsm = HopSlotMap::from(sm.clone());
+            for &(op, val) in &operations {
+                let k = keys.get(val as usize % keys.len().max(1)).copied();
+                match (op % 4, k) {
+                    (0, Some(k)) => {
+                        hsm.remove(k);
+                        sm.remove(k);
+                    }
+                    (1, Some(k)) => {
+                        if let Some(v) = hsm.get_mut(k) {
+                            *v = v.wrapping_add(1);
+                        }
+                    }
+                    (2, Some(k)) => {
+                        if let Some(v) = sm.get_mut(k) {
+                            *v = v.wrapping_add(1);
+                        }
+                    }
+                    _ => {
+                        keys.push(hsm.insert(val));
+                        keys.push(sm.insert(val));
+                    }
+                }
+
+                // Equality must only depend on the live key-value pairs, not on
+                // the type or history of either slot map.
+                let same = hsm.iter().collect::<HashMap<_, _>>() == sm.iter().collect();
+                let converted = HopSlotMap::from(sm.clone());
+                if (hsm == sm) != same || (sm == hsm) != same || (hsm == converted) != same {
+                    return false;
+                }
+            }
+
+            true
+        }
+    }
+
+    #[test]
+    fn get_unchecked_fragmented() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for &k in &keys[3..7] {
+            sm.remove(k);
+        }
+        let reused = sm.insert(42);
+
+        for &k in keys.iter().chain(core::iter::once(&reused)) {
+            if let Some(&v) = sm.get(k) {
+                assert_eq!(unsafe { *sm.get_unchecked(k) }, v);
+                unsafe { *sm.get_unchecked_mut(k) += 1 };
+                assert_eq!(sm[k], v + 1);
+            }
+        }
+        assert_eq!(sm[reused], 43);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic]
+    fn get_unchecked_stale_debug_assert() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert(1);
+        sm.remove(k);
+        sm.insert(2);
+        unsafe { sm.get_unchecked(k) };
+    }
+
+    #[test]
+    fn get2_mut() {
+        let mut sm = HopSlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+
+        // Aliasing, with the same key and with a stale key into the same slot.
+        assert_eq!(sm.get2_mut(a, a), None);
+        sm.remove(a);
+        let a2 = sm.insert(3);
+        assert_eq!(a.data().idx, a2.data().idx);
+        assert_eq!(sm.get2_mut(a2, a), None);
+        assert_eq!(sm.get2_mut(a, a2), None);
+
+        // Stale key into a different slot.
+        assert_eq!(sm.get2_mut(a, b), None);
+        assert_eq!(sm.get2_mut(b, a), None);
+
+        let (x, y) = sm.get2_mut(b, a2).unwrap();
+        core::mem::swap(x, y);
+        assert_eq!(sm[a2], 2);
+        assert_eq!(sm[b], 3);
+    }
+
+    #[test]
+    fn reserve_counts_vacant_slots() {
+        let mut sm = HopSlotMap::with_capacity(20);
+        assert_eq!(sm.capacity(), 20);
+        let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+        for &k in keys.iter().step_by(2) {
+            sm.remove(k);
+        }
+        assert_eq!(sm.len(), 10);
+
+        // The ten vacant slots count towards the reservation.
+        sm.reserve(10);
+        assert_eq!(sm.capacity(), 20);
+
+        sm.reserve(30);
+        let capacity = sm.capacity();
+        assert!(capacity >= 40);
+        let ptr = sm.slots.as_ptr();
+        for i in 0..30 {
+            sm.insert(100 + i);
+        }
+        assert_eq!(sm.capacity(), capacity);
+        assert_eq!(sm.slots.as_ptr(), ptr);
+        assert_eq!(sm.len(), 40);
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn clear_single_block() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[2]);
+        sm.remove(keys[6]);
+        sm.remove(keys[7]);
+
+        sm.clear();
+        assert_eq!(sm.len(), 0);
+        assert_eq!(sm.iter().next(), None);
+        assert!(keys.iter().all(|&k| !sm.contains_key(k)));
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // All slots form one vacant block with the sentinel.
+        assert_eq!(sm.blocks.len(), 1);
+        assert_eq!((sm.blocks[0].next, sm.blocks[0].back), (0, 10));
+
+        // New elements reuse the existing slots, under new versions.
+        let k = sm.insert(42);
+        assert_eq!(sm.slots.len(), 11);
+        assert!(!keys.contains(&k));
+        assert_eq!(sm[k], 42);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..1000).map(|i| sm.insert(i)).collect();
+        for &k in &keys[10..] {
+            sm.remove(k);
+        }
+        sm.remove(keys[3]);
+        sm.remove(keys[4]);
+
+        sm.shrink_to_fit();
+        assert_eq!(sm.slots.len(), 11);
+        assert!(sm.capacity() < 1000);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert!(keys[10..].iter().all(|&k| !sm.contains_key(k) && sm.get(k).is_none()));
+        assert_eq!(sm.len(), 8);
+
+        // Regrown slots don't bring back released keys.
+        let new_keys: Vec<_> = (0..1000).map(|i| sm.insert(i)).collect();
+        assert!(new_keys.iter().all(|k| !keys.contains(k)));
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // A trailing block that starts at the sentinel releases everything.
+        let guard = sm.slots[0].version;
+        sm.clear();
+        sm.shrink_to_fit();
+        assert_eq!(sm.slots.len(), 1);
+        assert!(sm.slots[0].version >= guard);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k = sm.insert(5);
+        assert!(!keys.contains(&k) && !new_keys.contains(&k));
+        assert_eq!(sm[k], 5);
+
+        // Nothing to release.
+        let mut sm = HopSlotMap::new();
+        sm.insert(1);
+        let k = sm.insert(2);
+        sm.shrink_to_fit();
+        assert_eq!(sm.slots.len(), 3);
+        assert_eq!(sm[k], 2);
+    }
+
+    #[test]
+    fn drain_filter() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+
+        {
+            let mut evens = sm.drain_filter(|_, v| *v % 2 == 0);
+            assert_eq!(evens.next(), Some((keys[0], 0)));
+            assert_eq!(evens.next(), Some((keys[2], 2)));
+        }
+
+        // Unvisited matches stay when the iterator is dropped early.
+        assert_eq!(sm.len(), 8);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert!(!sm.contains_key(keys[0]) && !sm.contains_key(keys[2]));
+        assert!(sm.contains_key(keys[4]));
+
+        // Consecutive removals merge into one vacant block.
+        let middle: Vec<_> = sm.drain_filter(|_, v| (3..8).contains(v)).map(|(_, v)| v).collect();
+        assert_eq!(middle, vec![3, 4, 5, 6, 7]);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![1, 8, 9]);
+
+        // The freed slots are reused, so the freelist is intact.
+        let new_keys: Vec<_> = (0..7).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.len(), 10);
+        assert_eq!(sm.slots.len(), 11);
+        assert!(new_keys.iter().all(|k| !keys.contains(k)));
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn exact_size_and_fused() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..30).map(|i| sm.insert(i)).collect();
+        for (i, &k) in keys.iter().enumerate() {
+            if i % 4 == 1 || (10..16).contains(&i) {
+                sm.remove(k);
+            }
+        }
+        let n = sm.len();
+
+        let mut iter = sm.iter();
+        for left in (0..n).rev() {
+            iter.next();
+            assert_eq!(iter.len(), left);
+        }
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next(), None);
+
+        let mut iter_mut = sm.iter_mut();
+        for left in (0..n).rev() {
+            iter_mut.next();
+            assert_eq!(iter_mut.len(), left);
+        }
+        assert!(iter_mut.next().is_none() && iter_mut.next().is_none());
+
+        assert_eq!(sm.keys().len(), n);
+        assert_eq!(sm.values().len(), n);
+        assert_eq!(sm.values_mut().len(), n);
+    }
+
+    #[test]
+    fn removal_order_merges_run() {
+        // Counts the vacant blocks by walking the freelist.
+        fn num_blocks<V>(sm: &HopSlotMap<DefaultKey, V>) -> usize {
+            let mut n = 0;
+            let mut id = 0;
+            loop {
+                id = sm.blocks[id as usize].next;
+                if id == 0 {
+                    return n;
+                }
+                n += 1;
+            }
+        }
+
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..50).map(|i| sm.insert(i)).collect();
+
+        // Remove slots 10..40 in a scattered order, 7 is coprime with 30.
+        for i in 0..30 {
+            sm.remove(keys[10 + (i * 7) % 30]);
+            assert_eq!(sm.check_invariants(), Ok(()));
+        }
+        assert_eq!(num_blocks(&sm), 1);
+        let block = sm.blocks[unsafe { sm.slots[11].u.block } as usize];
+        assert_eq!((block.prev, block.next, block.back), (0, 0, 40));
+
+        // A single hop takes iteration across the whole run.
+        let mut iter = sm.iter();
+        for _ in 0..10 {
+            iter.next();
+        }
+        assert_eq!(iter.cur, 11);
+        assert_eq!(iter.next().map(|(_, v)| *v), Some(40));
+        assert_eq!(iter.cur, 42);
+    }
+
+    #[test]
+    fn debug_output() {
+        let mut sm = HopSlotMap::new();
+        let foo = sm.insert("foo");
+        let tmp = sm.insert("tmp");
+        let bar = sm.insert("bar");
+        sm.remove(tmp);
+
+        let expected = format!("{{{:?}: \"foo\", {:?}: \"bar\"}}", foo, bar);
+        assert_eq!(format!("{:?}", sm), expected);
+        assert_eq!(format!("{:?}", sm.keys()), format!("[{:?}, {:?}]", foo, bar));
+        assert_eq!(format!("{:?}", sm.values()), "[\"foo\", \"bar\"]");
+
+        let mut iter = sm.iter();
+        iter.next();
+        assert_eq!(format!("{:?}", iter), format!("[({:?}, \"bar\")]", bar));
+        assert_eq!(format!("{:?}", sm.iter_mut()), "IterMut { remaining: 2 }");
+        assert_eq!(format!("{:?}", sm.clone().into_iter()), "IntoIter { remaining: 2 }");
+
+        let mut drain = sm.drain();
+        drain.next();
+        assert_eq!(format!("{:?}", drain), format!("[({:?}, \"bar\")]", bar));
+        drain.next();
+        assert_eq!(format!("{:?}", drain), "[]");
+    }
+
+    #[test]
+    fn extend() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for &k in &keys[2..6] {
+            sm.remove(k);
+        }
+
+        // The vacant block is filled before the storage grows.
+        sm.extend(vec![10, 11, 12, 13]);
+        assert_eq!(sm.len(), 10);
+        assert_eq!(sm.slots.len(), 11);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        sm.extend(&[20, 21]);
+        assert_eq!(sm.len(), 12);
+        assert_eq!(sm.slots.len(), 13);
+        let mut values: Vec<_> = sm.values().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![0, 1, 6, 7, 8, 9, 10, 11, 12, 13, 20, 21]);
+    }
+
+    #[cfg(has_min_const_generics)]
+    #[test]
+    fn get_disjoint_mut_fragmented() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..12).map(|i| sm.insert(i)).collect();
+        for &k in &keys[3..8] {
+            sm.remove(k);
+        }
+        let reused = sm.insert(100);
+        let (a, b, c) = (keys[0], keys[9], reused);
+
+        let [x, y, z] = sm.get_disjoint_mut([a, b, c]).unwrap();
+        core::mem::swap(x, z);
+        *y += 1;
+        assert_eq!((sm[a], sm[b], sm[c]), (100, 10, 0));
+
+        // Duplicates, stale keys and stale keys into reused slots.
+        assert_eq!(sm.get_disjoint_mut([a, b, a]), None);
+        assert_eq!(sm.get_disjoint_mut([a, keys[4], b]), None);
+        let stale = keys.iter().find(|k| k.data().idx == reused.data().idx).unwrap();
+        assert_eq!(sm.get_disjoint_mut([*stale, reused, a]), None);
+        assert_eq!(sm.get_disjoint_mut([reused, *stale, a]), None);
+
+        // Failed lookups leave every slot as it was.
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert!([a, b, c].iter().all(|&k| sm.contains_key(k)));
+        assert!(keys[3..8].iter().all(|&k| !sm.contains_key(k)));
+    }
+
+    #[test]
+    fn key_of() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10u64).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[4]);
+        sm.remove(keys[5]);
+        for (k, v) in &sm {
+            assert_eq!(sm.key_of(v), Some(k));
+        }
+        let vs: Vec<*const u64> = sm
+            .values_mut()
+            .map(|v| {
+                let p: *const u64 = v;
+                p
+            })
+            .collect();
+        assert!(vs.iter().zip(sm.keys()).all(|(&v, k)| sm.key_of(unsafe { &*v }) == Some(k)));
+
+        let mut other = HopSlotMap::new();
+        let ko = other.insert(0u64);
+        assert_eq!(sm.key_of(&other[ko]), None);
+        assert_eq!(sm.key_of(&0), None);
+
+        // Zero-sized values still have a unique address per slot.
+        let mut zst = HopSlotMap::new();
+        let a = zst.insert(());
+        let b = zst.insert(());
+        assert_eq!(zst.key_of(&zst[b]), Some(b));
+        assert_eq!(zst.key_of(&zst[a]), Some(a));
+    }
+
+    #[test]
+    fn reuse_policy() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..8).map(|i| sm.insert(i)).collect();
+        for &i in &[5, 1, 3] {
+            sm.remove(keys[i]);
+        }
+
+        // Fifo reuses the oldest block, not the one vacated last.
+        let idx = |sm: &mut HopSlotMap<DefaultKey, i32>| sm.insert(0).data().idx;
+        assert_eq!(idx(&mut sm), 6);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        sm.set_reuse_policy(ReusePolicy::Lifo);
+        sm.remove(keys[6]);
+        assert_eq!(idx(&mut sm), 7);
+        assert_eq!(idx(&mut sm), 4);
+        assert_eq!(idx(&mut sm), 2);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.len(), 8);
+
+        // Within a block slots are reused from the back.
+        let keys: Vec<_> = sm.keys().collect();
+        for k in &keys[1..4] {
+            sm.remove(*k);
+        }
+        assert_eq!(idx(&mut sm), 4);
+        assert_eq!(sm.clone().reuse_policy(), ReusePolicy::Lifo);
+    }
+
+    #[test]
+    fn insert_from_iter_matches_insert() {
+        for &policy in &[ReusePolicy::Fifo, ReusePolicy::Lifo] {
+            let mut sm = HopSlotMap::new();
+            sm.set_reuse_policy(policy);
+            let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+            for k in keys.iter().filter(|k| k.data().idx % 5 < 3) {
+                sm.remove(*k);
+            }
+            for k in &keys[17..] {
+                sm.remove(*k);
+            }
+
+            for n in &[0, 1, 4, 20, 30] {
+                let mut actual = sm.clone();
+                let mut expected = sm.clone();
+                let expected_keys: Vec<_> = (0..*n).map(|i| expected.insert(i)).collect();
+                assert_eq!(actual.insert_from_iter(0..*n), expected_keys);
+                assert!(actual.iter().eq(expected.iter()));
+                assert_eq!(actual.check_invariants(), Ok(()));
+                assert_eq!(actual.insert(-1), expected.insert(-1));
+            }
+        }
+    }
+
+    #[test]
+    fn insert_from_iter_panic() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+        for k in &keys[2..8] {
+            sm.remove(*k);
+        }
+
+        let values = (0..10).map(|i| if i == 3 { panic!() } else { i });
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sm.insert_from_iter(values);
+        }));
+        assert!(result.is_err());
+        assert_eq!(sm.len(), 7);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.insert_from_iter(0..4).len(), 4);
+        assert_eq!(sm.check_invariants(), Ok(()));
+
+        // The freelist is empty now, the values pushed before the panic stay.
+        let values = (0..10).map(|i| if i == 2 { panic!() } else { i });
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sm.insert_from_iter(values);
+        }));
+        assert!(result.is_err());
+        assert_eq!(sm.len(), 13);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        let k = sm.insert(42);
+        assert_eq!(sm[k], 42);
+        assert_eq!(sm.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn remove_many() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..30).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[12]);
+
+        // Runs touching existing blocks on either side, a run merging two
+        // blocks, isolated slots and duplicate and stale keys.
+        let mut batch: Vec<_> = [0, 1, 2, 9, 10, 11, 13, 14, 20, 25, 29]
+            .iter()
+            .map(|&i| keys[i])
+            .collect();
+        batch.extend_from_slice(&[keys[12], keys[20], keys[1]]);
+        let mut expected = sm.clone();
+        for k in &batch {
+            expected.remove(*k);
+        }
+
+        assert_eq!(sm.remove_many(&batch), 11);
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert!(sm.iter().eq(expected.iter()));
+        assert_eq!(sm.remove_many(&batch), 0);
+
+        // Every vacated slot is reused exactly once before the map grows.
+        let new_keys: Vec<_> = (0..12).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.slots.len(), 31);
+        let unique: HashSet<_> = new_keys.iter().map(|k| k.data().idx).collect();
+        assert_eq!(unique.len(), 12);
+        assert!(unique.iter().all(|&i| i <= 30));
+    }
+
+    quickcheck! {
+        fn qc_remove_many_equiv_remove(ops: Vec<(bool, u8)>) -> bool {
+            let mut sm = HopSlotMap::new();
+            let mut expected = HopSlotMap::new();
+            let mut keys = Vec::new();
+            for (insert, val) in ops {
+                if insert || keys.is_empty() {
+                    let k = sm.insert(val);
+                    if k != expected.insert(val) {
+                        return false;
+                    }
+                    keys.push(k);
+                } else {
+                    let start = val as usize % keys.len();
+                    let batch = &keys[start..(start + val as usize % 7).min(keys.len())];
+                    let n = batch.iter().filter(|k| expected.remove(**k).is_some()).count();
+                    if sm.remove_many(batch) != n || sm.check_invariants().is_err() {
+                        return false;
+                    }
+                    // Reuse order may differ, so continue from equal layouts.
+                    expected = sm.clone();
+                }
+            }
+            sm.iter().eq(expected.iter())
+        }
+    }
+
+    #[test]
+    fn compact() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        let mut live = HashMap::new();
+        for (i, k) in keys.iter().enumerate() {
+            if i % 3 == 0 {
+                live.insert(*k, i);
+            } else {
+                sm.remove(*k);
+            }
+        }
+
+        let mut moved = 0;
+        sm.compact(|old, new, v| {
+            assert_eq!(live.remove(&old), Some(*v));
+            assert!(new.data().idx < old.data().idx);
+            live.insert(new, *v);
+            *v += 1000;
+            moved += 1;
+        });
+
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.slots.len(), 35);
+        assert_eq!(sm.blocks.len(), 1);
+        // Values in slots 1, 4, ..., 34 stay in place, the other 22 move.
+        assert_eq!(moved, 22);
+        for (k, i) in &live {
+            let expected = if keys.contains(k) { *i } else { *i + 1000 };
+            assert_eq!(sm[*k], expected);
+        }
+
+        // Old keys stay invalid when their slot comes back.
+        let new: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        for k in &keys {
+            if !live.contains_key(k) {
+                assert!(!sm.contains_key(*k));
+            }
+        }
+        for (i, k) in new.iter().enumerate() {
+            assert_eq!(sm[*k], i);
+        }
+
+        let mut empty: HopSlotMap<DefaultKey, i32> = HopSlotMap::new();
+        empty.compact(|_, _, _| panic!());
+        assert_eq!(empty.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn slot_size() {
+        use std::mem::size_of;
+
+        // Occupied slots cost as much as in a SlotMap, block metadata is
+        // stored separately.
+        assert_eq!(size_of::<Slot<u32>>(), 8);
+        assert_eq!(size_of::<Slot<u8>>(), 8);
+        assert_eq!(size_of::<Slot<u64>>(), 16);
+        assert_eq!(size_of::<Slot<[u8; 12]>>(), 16);
+    }
+
+    #[test]
+    fn vacant_blocks() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..20).map(|i| sm.insert(i)).collect();
+        assert_eq!(sm.vacant_blocks().count(), 0);
+
+        // Leading slots join the sentinel's block, trailing ones form their
+        // own.
+        for &i in &[0, 1, 5, 6, 7, 12, 19] {
+            sm.remove(keys[i]);
+        }
+        let mut blocks: Vec<_> = sm.vacant_blocks().collect();
+        blocks.sort();
+        assert_eq!(blocks, vec![(1, 2), (6, 3), (13, 1), (20, 1)]);
+        assert_eq!(sm.fragmentation(), (4, 3));
+
+        let total: u32 = blocks.iter().map(|&(_, len)| len).sum();
+        assert_eq!(total as usize, sm.slots.len() - 1 - sm.len());
+    }
+
+    #[test]
+    fn try_insert_full() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert(0);
+        sm.remove(k);
+
+        // Pretend the map is one element away from full. There is a vacant slot
+        // so this does not need to allocate.
+        sm.num_elems = u32::MAX - 2;
+        assert!(!sm.is_full());
+        let k = sm.try_insert(1).unwrap();
+        assert_eq!(sm[k], 1);
+
+        assert!(sm.is_full());
+        assert_eq!(sm.try_insert(2), Err(2));
+        assert_eq!(sm.len(), u32::MAX as usize - 1);
+        assert_eq!(sm.slots.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "number of elements overflow")]
+    fn insert_full() {
+        let mut sm = HopSlotMap::new();
+        sm.num_elems = u32::MAX - 1;
+        sm.insert(0);
+    }
+
+    #[test]
+    fn into_keys_values() {
+        let drops = std::cell::RefCell::new(0usize);
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..10).map(|_| sm.insert(CountDrop(&drops))).collect();
+        sm.remove(keys[3]);
+        sm.remove(keys[4]);
+        assert_eq!(*drops.borrow(), 2);
+
+        let mut values = sm.clone().into_values();
+        assert_eq!(values.len(), 8);
+        values.next();
+        assert_eq!(*drops.borrow(), 3);
+        assert_eq!(values.len(), 7);
+        drop(values);
+        assert_eq!(*drops.borrow(), 10);
+
+        let mut into_keys = sm.into_keys();
+        assert_eq!(into_keys.size_hint(), (8, Some(8)));
+        assert_eq!(into_keys.next(), Some(keys[0]));
+        assert_eq!(*drops.borrow(), 11);
+        let rest: Vec<_> = into_keys.collect();
+        assert_eq!(rest.len(), 7);
+        assert!(!rest.contains(&keys[3]) && !rest.contains(&keys[4]));
+        assert_eq!(*drops.borrow(), 18);
+    }
+
+    #[test]
+    fn clear_and_shrink() {
+        let mut sm = HopSlotMap::new();
+        let mut old = Vec::new();
+        for round in 0..3 {
+            // Churn a few slots so versions differ between slots.
+            let keys: Vec<_> = (0..50).map(|i| sm.insert(i)).collect();
+            for k in keys.iter().take(round * 10) {
+                sm.remove(*k);
+                old.push(*k);
+                old.push(sm.insert(0));
+            }
+            old.extend(keys);
+
+            sm.clear_and_shrink();
+            assert!(sm.is_empty());
+            assert_eq!(sm.slots.len(), 1);
+            assert_eq!(sm.blocks.len(), 1);
+            assert_eq!(sm.check_invariants(), Ok(()));
+            assert!(old.iter().all(|&k| !sm.contains_key(k)));
+        }
+
+        // Regrowing past the old length never revives an old key.
+        let new: Vec<_> = (0..100).map(|i| sm.insert(i)).collect();
+        assert!(old.iter().all(|&k| !sm.contains_key(k)));
+        assert!(new.iter().all(|&k| sm.contains_key(k)));
+    }
+
+    #[test]
+    fn foreign_keys_out_of_bounds() {
+        let mut big = HopSlotMap::new();
+        let keys: Vec<_> = (0..100).map(|i| big.insert(i)).collect();
+
+        let mut sm = HopSlotMap::new();
+        sm.insert(0);
+        let large = [
+            KeyData::from_ffi(u64::from(u32::MAX - 1) | (1 << 32)),
+            KeyData::from_ffi(u64::MAX),
+            KeyData::null(),
+        ];
+        for key in keys.into_iter().chain(large.iter().map(|&kd| kd.into())) {
+            if key.data().idx < 2 {
+                continue;
+            }
+            assert!(!sm.contains_key(key));
+            assert_eq!(sm.get(key), None);
+            assert_eq!(sm.get_mut(key), None);
+            assert_eq!(sm.get2_mut(key, key), None);
+            assert_eq!(sm.remove_if(key, |_| true), None);
+            assert_eq!(sm.remove_many(&[key]), 0);
+            assert_eq!(sm.remove(key), None);
+        }
+        assert_eq!(sm.len(), 1);
+        sm.check_invariants().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "key is out of range")]
+    fn index_out_of_bounds_key() {
+        let mut sm = HopSlotMap::new();
+        sm.insert(0);
+        let key: DefaultKey = KeyData::from_ffi(u64::from(u32::MAX - 1) | (1 << 32)).into();
+        let _ = sm[key];
+    }
+
+    #[test]
+    fn convert_from_slot_map() {
+        let mut sm = SlotMap::new();
+        let mut keys = Vec::new();
+        for round in 0..5 {
+            keys.extend((0..50).map(|i| sm.insert(round * 100 + i)));
+            for k in keys.iter().skip(round as usize).step_by(3) {
+                sm.remove(*k);
+            }
+        }
+        // Release the tail slots so the sentinel guards their versions.
+        for k in keys.iter().rev().take(10) {
+            sm.remove(*k);
+        }
+        sm.shrink_to_fit();
+        let reserved = sm.reserve_key().key();
+        let expected: Vec<_> = keys.iter().map(|&k| sm.get(k).copied()).collect();
+
+        let mut hsm = HopSlotMap::from(sm);
+        hsm.check_invariants().unwrap();
+        assert_eq!(hsm.len(), expected.iter().flatten().count());
+        assert!(!hsm.contains_key(reserved));
+        for (&k, &v) in keys.iter().zip(&expected) {
+            assert_eq!(hsm.contains_key(k), v.is_some());
+            assert_eq!(hsm.get(k).copied(), v);
+        }
+
+        // Stale and reserved keys stay invalid after the vacant slots are
+        // reused.
+        let new: Vec<_> = (0..100).map(|i| hsm.insert(1000 + i)).collect();
+        hsm.check_invariants().unwrap();
+        assert!(!new.contains(&reserved));
+        assert!(new.iter().any(|k| k.data().idx == reserved.data().idx));
+        assert!(!hsm.contains_key(reserved));
+        for (&k, &v) in keys.iter().zip(&expected) {
+            assert_eq!(hsm.get(k).copied(), v);
+        }
+
+        // And back again.
+        let sm = SlotMap::from(hsm.clone());
+        sm.check_invariants().unwrap();
+        assert_eq!(sm, hsm);
+        for &k in keys.iter().chain(&new) {
+            assert_eq!(sm.get(k), hsm.get(k));
+        }
+    }
+
+    #[test]
+    fn for_loop_by_ref() {
+        let mut sm = HopSlotMap::new();
+        let a = sm.insert(1);
+        let b = sm.insert(2);
+        for (_, v) in &mut sm {
+            *v *= 10;
+        }
+        let mut seen = Vec::new();
+        for (k, v) in &sm {
+            seen.push((k, *v));
+        }
+        assert_eq!(seen, vec![(a, 10), (b, 20)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid HopSlotMap key Key(1v1): key is stale, slot currently at version 3")]
+    fn index_stale_key() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert(1);
+        sm.remove(k);
+        sm.insert(2);
+        sm[k] = 3;
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid HopSlotMap key Key(2v1): key is out of range, slot does not exist")]
+    fn index_out_of_range_key() {
+        let mut other = HopSlotMap::new();
+        other.insert(1);
+        let k = other.insert(2);
+        let sm: HopSlotMap<_, i32> = HopSlotMap::new();
+        let _ = sm[k];
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid HopSlotMap key Key(4294967295v1): key is null")]
+    fn index_null_key() {
+        let sm: HopSlotMap<DefaultKey, i32> = HopSlotMap::new();
+        let _ = sm[DefaultKey::null()];
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid HopSlotMap key Key(4294967295v1): key is null")]
+    fn index_mut_null_key() {
+        let mut sm: HopSlotMap<DefaultKey, (i32, i32)> = HopSlotMap::new();
+        sm.insert((1, 2));
+        sm[DefaultKey::null()].1 -= 1;
+    }
+
+    #[test]
+    fn index_mut_field() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert((10, 100));
+        sm[k].1 -= 30;
+        sm[k].0 += 1;
+        assert_eq!(sm[k], (11, 70));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde() {
+        let mut sm = HopSlotMap::new();
+        // Self-referential structure.
+        let first = sm.insert_with_key(|k| (k, 23i32));
+        let second = sm.insert((first, 42));
+
+        // Make some empty slots.
+        let empties = vec![sm.insert((first, 0)), sm.insert((first, 0))];
+        empties.iter().for_each(|k| {
+            sm.remove(*k);
+        });
+
+        let third = sm.insert((second, 0));
+        sm[first].0 = third;
+
+        let ser = serde_json::to_string(&sm).unwrap();
+        let de: HopSlotMap<DefaultKey, (DefaultKey, i32)> = serde_json::from_str(&ser).unwrap();
+        assert_eq!(de.len(), sm.len());
+
+        let mut smkv: Vec<_> = sm.iter().collect();
+        let mut dekv: Vec<_> = de.iter().collect();
+        smkv.sort();
+        dekv.sort();
+        assert_eq!(smkv, dekv);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_freelist_tail() {
+        let mut sm = HopSlotMap::new();
+        let keys: Vec<_> = (0..4).map(|i| sm.insert(i)).collect();
+        sm.remove(keys[1]);
+
+        let ser = serde_json::to_string(&sm).unwrap();
+        let mut de: HopSlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+        assert_eq!(de.check_invariants(), Ok(()));
+
+        // A new block is appended after the tail, which must not drop the
+        // block deserialized earlier.
+        de.remove(keys[3]);
+        assert_eq!(de.check_invariants(), Ok(()));
+        de.insert(10);
+        de.insert(11);
+        assert_eq!(de.len(), 4);
+        assert_eq!(de.slots.len(), 5);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_freelist() {
+        let mut sm = HopSlotMap::new();
+        let k = sm.insert(5i32);
+        sm.remove(k);
+
+        let ser = serde_json::to_string(&sm).unwrap();
+        let mut de: HopSlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+
+        de.insert(0);
+        de.insert(1);
         de.insert(2);
         assert_eq!(de.len(), 3);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_fragmented() {
+        let patterns: [fn(usize) -> bool; 4] = [
+            |i| i % 2 == 0,
+            |i| i % 7 < 3,
+            |i| !(5..=25).contains(&i),
+            |i| (11..20).contains(&i),
+        ];
+
+        for removed in patterns.iter() {
+            let mut sm = HopSlotMap::new();
+            let keys: Vec<_> = (0..30).map(|i| sm.insert(i)).collect();
+            for (i, &k) in keys.iter().enumerate() {
+                if removed(i) {
+                    sm.remove(k);
+                }
+            }
+
+            let ser = serde_json::to_string(&sm).unwrap();
+            let mut de: HopSlotMap<DefaultKey, i32> = serde_json::from_str(&ser).unwrap();
+            assert_eq!(de.check_invariants(), Ok(()));
+            assert_eq!(de, sm);
+            assert!(de.iter().eq(sm.iter()));
+            assert!(de.iter().rev().eq(sm.iter().rev()));
+            assert!(keys.iter().all(|&k| de.get(k) == sm.get(k)));
+
+            // New elements fill the vacant slots under fresh keys.
+            let n = keys.len() - de.len();
+            let new_keys: Vec<_> = (0..n).map(|i| de.insert(100 + i as i32)).collect();
+            assert_eq!(de.slots.len(), 31);
+            assert!(new_keys.iter().all(|k| !keys.contains(k)));
+            assert_eq!(de.check_invariants(), Ok(()));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn slotmap_serde_malicious() {
+        type Sm = HopSlotMap<DefaultKey, i32>;
+        let inconsistent = r#"[{"value":null,"version":0},{"value":5,"version":2}]"#;
+        assert!(serde_json::from_str::<Sm>(inconsistent).is_err());
+        let missing = r#"[{"value":null,"version":0},{"value":null,"version":3}]"#;
+        assert!(serde_json::from_str::<Sm>(missing).is_err());
+        let occupied_sentinel = r#"[{"value":1,"version":1}]"#;
+        assert!(serde_json::from_str::<Sm>(occupied_sentinel).is_err());
+        assert!(serde_json::from_str::<Sm>("[]").is_err());
+
+        let zero_version = r#"[{"value":null,"version":0},{"value":null,"version":0},{"value":7,"version":5}]"#;
+        assert!(serde_json::from_str::<Sm>(zero_version).is_err());
+
+        let ok = r#"[{"value":null,"version":0},{"value":null,"version":2},{"value":7,"version":5}]"#;
+        let mut sm: Sm = serde_json::from_str(ok).unwrap();
+        assert_eq!(sm.check_invariants(), Ok(()));
+        assert_eq!(sm.len(), 1);
+        let k = sm.insert(8);
+        assert_eq!(k.data().idx, 1);
+        assert_eq!(sm.values().copied().collect::<Vec<_>>(), vec![8, 7]);
+    }
 }
@@ -101,8 +101,10 @@
 //! ```
 //!
 //! Unfortunately [`SparseSecondaryMap`] is not available in [`no_std`], because
-//! it relies on [`HashMap`]. Finally the `unstable` feature can be defined to
-//! enable the parts of `slotmap` that only work on nightly Rust.
+//! it relies on [`HashMap`]. The `rayon` feature adds parallel iterators over
+//! the values of a [`DenseSlotMap`] through the [`rayon`] library. Finally the
+//! `unstable` feature can be defined to enable the parts of `slotmap` that
+//! only work on nightly Rust.
 //!
 //! # Why not index a [`Vec`], or use [`slab`], [`stable-vec`], etc?
 //!
@@ -130,18 +132,19 @@
 //! circumstances is the behavior safe. A slot map can hold up to
 //! 2<sup>32</sup> - 2 elements at a time.
 //!
-//! The memory usage for each slot in [`SlotMap`] is `4 + max(sizeof(T), 4)`
-//! rounded up to the alignment of `T`. Similarly it is `4 + max(sizeof(T), 12)`
-//! for [`HopSlotMap`]. [`DenseSlotMap`] has an overhead of 8 bytes per element
-//! and 8 bytes per slot.
+//! The memory usage for each slot in [`SlotMap`] and [`HopSlotMap`] is
+//! `4 + max(sizeof(T), 4)` rounded up to the alignment of `T`. On top of that
+//! [`HopSlotMap`] uses 16 bytes for every block of contiguous vacant slots.
+//! [`DenseSlotMap`] has an overhead of 8 bytes per element and 8 bytes per
+//! slot.
 //!
 //! # Choosing [`SlotMap`], [`HopSlotMap`] or [`DenseSlotMap`]
 //!
 //! A [`SlotMap`] is the fastest for most operations, except iteration. It can
-//! never shrink the size of its underlying storage, because it must remember
-//! for each storage slot what the latest stored version was, even if the slot
-//! is empty now. This means that iteration can be slow as it must iterate over
-//! potentially a lot of empty slots.
+//! not shrink its underlying storage past the last occupied slot, because it
+//! must remember for each storage slot what the latest stored version was,
+//! even if the slot is empty now. This means that iteration can be slow as it
+//! must iterate over potentially a lot of empty slots.
 //!
 //! [`HopSlotMap`] solves this by maintaining more information on
 //! insertion/removal, allowing it to iterate only over filled slots by 'hopping
@@ -200,6 +203,7 @@
 //! [`BTreeMap`]: std::collections::BTreeMap
 //! [`HashMap`]: std::collections::HashMap
 //! [`serde`]: https://github.com/serde-rs/serde
+//! [`rayon`]: https://github.com/rayon-rs/rayon
 //! [`slab`]: https://crates.io/crates/slab
 //! [`stable-vec`]: https://crates.io/crates/stable-vec
 //! [`no_std`]: https://doc.rust-lang.org/1.7.0/book/no-stdlib.html
@@ -216,6 +220,7 @@ pub mod __impl {
 pub mod basic;
 pub mod dense;
 pub mod hop;
+pub mod observed;
 pub mod secondary;
 #[cfg(feature = "std")]
 pub mod sparse_secondary;
@@ -303,6 +308,16 @@ impl KeyData {
         let version = (value >> 32) | 1; // Ensure version is odd.
         Self::new(idx as u32, version as u32)
     }
+
+    /// Returns the index of the slot this key refers to. Keys that refer to
+    /// the same slot but were handed out at different times have the same
+    /// index, but differ in their version.
+    ///
+    /// The index can be resolved to the current key and value of its slot
+    /// with [`SlotMap::get_by_index`].
+    pub fn index(self) -> u32 {
+        self.idx
+    }
 }
 
 impl Debug for KeyData {
@@ -320,7 +335,9 @@ impl Default for KeyData {
 /// Key used to access stored values in a slot map.
 ///
 /// Do not use a key from one slot map in another. The behavior is safe but
-/// non-sensical (and might panic in case of out-of-bounds).
+/// non-sensical. A key whose index is out of bounds for a map is simply
+/// treated as invalid by methods such as `get`, `get_mut`, `contains_key`
+/// and `remove`. Only indexing with `map[key]` panics on an invalid key.
 ///
 /// To prevent this, it is suggested to have a unique key type for each slot
 /// map. You can create new key types using [`new_key_type!`], which makes a
@@ -506,6 +523,290 @@ new_key_type! {
     pub struct DefaultKey;
 }
 
+/// The core operations shared by [`SlotMap`], [`HopSlotMap`] and
+/// [`DenseSlotMap`], allowing code to be generic over the slot map
+/// implementation.
+///
+/// Every method behaves exactly like the inherent method of the same name.
+/// Iteration is provided by the [`SlotMapIter`] supertrait.
+///
+/// # Examples
+///
+/// ```
+/// # use slotmap::*;
+/// fn total<M: SlotMapLike<DefaultKey, u32>>(m: &M) -> u32 {
+///     m.iter().map(|(_, v)| *v).sum()
+/// }
+///
+/// fn fill<M: SlotMapLike<DefaultKey, u32>>(mut m: M) -> u32 {
+///     let a = m.insert(1);
+///     m.insert(2);
+///     *m.get_mut(a).unwrap() += 10;
+///     total(&m)
+/// }
+///
+/// assert_eq!(fill(SlotMap::new()), 13);
+/// assert_eq!(fill(HopSlotMap::new()), 13);
+/// assert_eq!(fill(DenseSlotMap::new()), 13);
+/// ```
+pub trait SlotMapLike<K: Key, V>: for<'a> SlotMapIter<'a, K, V> {
+    /// Inserts a value into the slot map, returning its key. See
+    /// [`SlotMap::insert`].
+    fn insert(&mut self, value: V) -> K;
+
+    /// Returns a reference to the value corresponding to the key. See
+    /// [`SlotMap::get`].
+    fn get(&self, key: K) -> Option<&V>;
+
+    /// Returns a mutable reference to the value corresponding to the key. See
+    /// [`SlotMap::get_mut`].
+    fn get_mut(&mut self, key: K) -> Option<&mut V>;
+
+    /// Removes a key from the slot map, returning the value at the key if the
+    /// key was not previously removed. See [`SlotMap::remove`].
+    fn remove(&mut self, key: K) -> Option<V>;
+
+    /// Returns [`true`] if the slot map contains `key`. See
+    /// [`SlotMap::contains_key`].
+    fn contains_key(&self, key: K) -> bool;
+
+    /// Returns the number of elements in the slot map. See [`SlotMap::len`].
+    fn len(&self) -> usize;
+
+    /// Returns if the slot map is empty. See [`SlotMap::is_empty`].
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Iteration over the elements of a slot map borrowed for `'a`, the
+/// supertrait of [`SlotMapLike`] that provides its iterator types.
+///
+/// The last type parameter is never specified, it bounds `'a` by the
+/// lifetime of `Self`.
+pub trait SlotMapIter<'a, K: Key, V: 'a, Outlives = &'a Self> {
+    /// The iterator type returned by [`iter`](Self::iter).
+    type Iter: Iterator<Item = (K, &'a V)>;
+
+    /// The iterator type returned by [`iter_mut`](Self::iter_mut).
+    type IterMut: Iterator<Item = (K, &'a mut V)>;
+
+    /// An iterator visiting all key-value pairs. See [`SlotMap::iter`].
+    fn iter(&'a self) -> Self::Iter;
+
+    /// An iterator visiting all key-value pairs, with mutable references to
+    /// the values. See [`SlotMap::iter_mut`].
+    fn iter_mut(&'a mut self) -> Self::IterMut;
+}
+
+/// Implements [`SlotMapLike`] and [`SlotMapIter`] for a slot map type by
+/// forwarding to its inherent methods.
+macro_rules! impl_slot_map_like {
+    ($name:ident, $module:ident) => {
+        impl<K: Key, V> SlotMapLike<K, V> for $name<K, V> {
+            fn insert(&mut self, value: V) -> K {
+                $name::insert(self, value)
+            }
+
+            fn get(&self, key: K) -> Option<&V> {
+                $name::get(self, key)
+            }
+
+            fn get_mut(&mut self, key: K) -> Option<&mut V> {
+                $name::get_mut(self, key)
+            }
+
+            fn remove(&mut self, key: K) -> Option<V> {
+                $name::remove(self, key)
+            }
+
+            fn contains_key(&self, key: K) -> bool {
+                $name::contains_key(self, key)
+            }
+
+            fn len(&self) -> usize {
+                $name::len(self)
+            }
+        }
+
+        impl<'a, K: Key, V> SlotMapIter<'a, K, V> for $name<K, V> {
+            type Iter = $module::Iter<'a, K, V>;
+            type IterMut = $module::IterMut<'a, K, V>;
+
+            fn iter(&'a self) -> Self::Iter {
+                $name::iter(self)
+            }
+
+            fn iter_mut(&'a mut self) -> Self::IterMut {
+                $name::iter_mut(self)
+            }
+        }
+    };
+}
+
+impl_slot_map_like!(SlotMap, basic);
+impl_slot_map_like!(HopSlotMap, hop);
+impl_slot_map_like!(DenseSlotMap, dense);
+
+/// The order in which a slot map reuses vacant slots, see
+/// [`SlotMap::set_reuse_policy`] and [`HopSlotMap::set_reuse_policy`].
+///
+/// Every reuse of a slot bumps its version. Spreading reuse over all vacant
+/// slots with [`ReusePolicy::Fifo`] keeps the versions of individual slots
+/// low, so it takes longer before a slot's version wraps around and an old key
+/// could become valid again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReusePolicy {
+    /// The most recently vacated slot is reused first.
+    Lifo,
+
+    /// The slot that has been vacant the longest is reused first.
+    Fifo,
+}
+
+/// An internal inconsistency found by the `check_invariants` method of a slot
+/// map, for example [`SlotMap::check_invariants`].
+///
+/// Indices refer to slots in the underlying storage, where slot 0 is a
+/// sentinel that never holds a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum InvariantError {
+    /// The sentinel slot is occupied.
+    SentinelOccupied,
+
+    /// The freelist links to a slot that does not exist.
+    FreeListOutOfBounds {
+        /// The index of the slot that was linked to.
+        idx: u32,
+    },
+
+    /// The freelist links to an occupied slot.
+    FreeListOccupied {
+        /// The index of the occupied slot.
+        idx: u32,
+    },
+
+    /// The freelist contains a cycle.
+    FreeListCycle,
+
+    /// The freelist tail does not point to the last slot on the freelist.
+    FreeListTail {
+        /// The index of the slot the tail points to.
+        idx: u32,
+    },
+
+    /// A vacant slot can not be reached through the freelist.
+    UnreachableVacant {
+        /// The index of the vacant slot.
+        idx: u32,
+    },
+
+    /// The metadata of a block of vacant slots in a [`HopSlotMap`] does not
+    /// describe the slots as they are.
+    InvalidBlock {
+        /// The index of the first slot of the block.
+        idx: u32,
+    },
+
+    /// The stored bound on the highest occupied slot of a [`SlotMap`] lies
+    /// below an occupied slot or past the last slot.
+    MaxOccupied {
+        /// The stored bound on the index of the highest occupied slot.
+        idx: u32,
+    },
+
+    /// A slot other than the sentinel stores version 0, which is reserved for
+    /// the sentinel.
+    ZeroVersion {
+        /// The index of the slot.
+        idx: u32,
+    },
+
+    /// The stored number of elements does not match the number of occupied
+    /// slots.
+    LenMismatch {
+        /// The stored number of elements.
+        len: usize,
+        /// The number of occupied slots.
+        occupied: usize,
+    },
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            InvariantError::SentinelOccupied => write!(f, "sentinel slot is occupied"),
+            InvariantError::FreeListOutOfBounds { idx } => {
+                write!(f, "freelist links to slot {} which does not exist", idx)
+            },
+            InvariantError::FreeListOccupied { idx } => {
+                write!(f, "freelist links to occupied slot {}", idx)
+            },
+            InvariantError::FreeListCycle => write!(f, "freelist contains a cycle"),
+            InvariantError::FreeListTail { idx } => {
+                write!(f, "freelist tail points to slot {} which is not the last", idx)
+            },
+            InvariantError::UnreachableVacant { idx } => {
+                write!(f, "vacant slot {} is not on the freelist", idx)
+            },
+            InvariantError::InvalidBlock { idx } => {
+                write!(f, "vacant block starting at slot {} is inconsistent", idx)
+            },
+            InvariantError::MaxOccupied { idx } => {
+                write!(f, "slot {} is not a bound on the occupied slots", idx)
+            },
+            InvariantError::ZeroVersion { idx } => write!(f, "slot {} has version 0", idx),
+            InvariantError::LenMismatch { len, occupied } => write!(
+                f,
+                "length is {} but {} slots are occupied",
+                len, occupied
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvariantError {}
+
+/// The reason a key lookup failed, returned by lookups such as
+/// [`SlotMap::try_get`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum KeyError {
+    /// The key is the null key.
+    Null,
+
+    /// The key refers to an existing slot, but the slot has a different
+    /// version. The value the key referred to was removed, and the slot may
+    /// have been reused since.
+    Stale {
+        /// The version the slot is currently at.
+        current_version: u32,
+    },
+
+    /// The key refers to a slot that does not exist. This happens when using
+    /// a key from a different slot map.
+    OutOfBounds,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            KeyError::Null => write!(f, "key is null"),
+            KeyError::Stale { current_version } => write!(
+                f,
+                "key is stale, slot is currently at version {}",
+                current_version
+            ),
+            KeyError::OutOfBounds => write!(f, "key is out of bounds"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for KeyError {}
+
 // Serialization with serde.
 #[cfg(feature = "serde")]
 mod serialize {
@@ -577,6 +878,72 @@ mod tests {
         assert!(is_older(u32::MAX, 0));
     }
 
+    #[test]
+    fn slot_map_like() {
+        use super::*;
+
+        fn exercise<'s, M: SlotMapLike<DefaultKey, &'s str>>(mut m: M, s: &'s str) {
+            assert!(m.is_empty());
+            let a = m.insert(&s[..1]);
+            let b = m.insert(&s[1..]);
+            assert_eq!(m.len(), 2);
+            for (k, v) in m.iter_mut() {
+                if k == a {
+                    *v = s;
+                }
+            }
+            assert_eq!(m.get(a), Some(&s));
+            assert_eq!(m.remove(b), Some(&s[1..]));
+            assert!(!m.contains_key(b));
+            assert_eq!(m.get_mut(b), None);
+            assert_eq!(m.iter().map(|(k, _)| k).collect::<Vec<_>>(), vec![a]);
+        }
+
+        // The values borrow from a local, so they are not 'static.
+        let s = String::from("hello");
+        exercise(SlotMap::new(), &s);
+        exercise(HopSlotMap::new(), &s);
+        exercise(DenseSlotMap::new(), &s);
+    }
+
+    #[test]
+    fn iteration_order() {
+        use super::*;
+
+        // Dense slot maps move elements on removal, so their order differs.
+        fn check<M>(new: fn() -> M)
+        where
+            M: SlotMapLike<DefaultKey, u32> + Clone + IntoIterator<Item = (DefaultKey, u32)>,
+            for<'a> <M as SlotMapIter<'a, DefaultKey, u32>>::Iter: DoubleEndedIterator,
+        {
+            let churn = || {
+                let mut m = new();
+                let mut keys = Vec::new();
+                for i in 0..200u32 {
+                    keys.push(m.insert(i));
+                    if i % 3 == 0 {
+                        let k = keys[(i as usize * 7) % keys.len()];
+                        m.remove(k);
+                    }
+                }
+                m
+            };
+
+            let a = churn();
+            let b = churn();
+            let idx: Vec<_> = a.iter().map(|(k, _)| k.data().idx).collect();
+            assert!(idx.windows(2).all(|w| w[0] < w[1]));
+            assert!(a.iter().eq(b.iter()));
+            assert!(a.iter().map(|(k, _)| k).eq(a.clone().into_iter().map(|(k, _)| k)));
+
+            let back: Vec<_> = a.iter().rev().map(|(k, _)| k.data().idx).collect();
+            assert!(back.windows(2).all(|w| w[0] > w[1]));
+        }
+
+        check(SlotMap::new);
+        check(HopSlotMap::new);
+    }
+
     #[test]
     fn iters_cloneable() {
         use super::*;
@@ -0,0 +1,559 @@
+//! Contains a slot map wrapper that reports every removed element.
+//!
+//! An [`ObservedSlotMap`] behaves like a regular [`SlotMap`], but invokes a
+//! hook with the key and value of every element it removes, just before the
+//! value is returned or dropped. This is useful when values own resources that
+//! can not be released by [`Drop`], such as handles into a foreign API.
+//!
+//! [`SlotMap`]: crate::SlotMap
+
+use core::fmt;
+use core::iter::FusedIterator;
+use core::ops::{Index, IndexMut};
+
+use crate::basic::{self, Iter, IterMut, Keys, Values, ValuesMut};
+use crate::{DefaultKey, Key, SlotMap};
+
+/// A [`SlotMap`] that calls a hook for every element it removes.
+///
+/// The hook is called exactly once per removed element, whether it was
+/// removed by [`remove`], [`remove_if`], [`remove_many`], [`retain`],
+/// [`clear`], [`drain`] or [`drain_filter`]. It receives the key and a
+/// reference to the value while the value is still alive.
+///
+/// The hook is not called when the [`ObservedSlotMap`] itself is dropped or
+/// turned back into a [`SlotMap`] with [`into_inner`]. Call [`clear`] first if
+/// the remaining elements should be reported as well.
+///
+/// [`remove`]: ObservedSlotMap::remove
+/// [`remove_if`]: ObservedSlotMap::remove_if
+/// [`remove_many`]: ObservedSlotMap::remove_many
+/// [`retain`]: ObservedSlotMap::retain
+/// [`clear`]: ObservedSlotMap::clear
+/// [`drain`]: ObservedSlotMap::drain
+/// [`drain_filter`]: ObservedSlotMap::drain_filter
+/// [`into_inner`]: ObservedSlotMap::into_inner
+///
+/// # Examples
+///
+/// ```
+/// # use slotmap::*;
+/// # use slotmap::observed::ObservedSlotMap;
+/// use std::cell::RefCell;
+///
+/// let released = RefCell::new(Vec::new());
+/// let mut sm = ObservedSlotMap::new(|_, handle: &u32| released.borrow_mut().push(*handle));
+/// let a = sm.insert(7);
+/// sm.insert(8);
+///
+/// sm.remove(a);
+/// assert_eq!(*released.borrow(), vec![7]);
+/// sm.clear();
+/// assert_eq!(*released.borrow(), vec![7, 8]);
+/// ```
+pub struct ObservedSlotMap<K: Key, V, F> {
+    sm: SlotMap<K, V>,
+    on_remove: F,
+}
+
+impl<V, F> ObservedSlotMap<DefaultKey, V, F>
+where
+    F: FnMut(DefaultKey, &V),
+{
+    /// Constructs a new, empty [`ObservedSlotMap`] calling `on_remove` for
+    /// every removed element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut sm = ObservedSlotMap::new(|_, _: &i32| {});
+    /// ```
+    pub fn new(on_remove: F) -> Self {
+        Self::from_slot_map(SlotMap::new(), on_remove)
+    }
+}
+
+impl<K: Key, V, F> ObservedSlotMap<K, V, F>
+where
+    F: FnMut(K, &V),
+{
+    /// Constructs a new, empty [`ObservedSlotMap`] with a custom key type,
+    /// calling `on_remove` for every removed element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// new_key_type! {
+    ///     struct TextureKey;
+    /// }
+    /// let mut textures = ObservedSlotMap::with_key(|_: TextureKey, _: &u32| {});
+    /// ```
+    pub fn with_key(on_remove: F) -> Self {
+        Self::from_slot_map(SlotMap::with_key(), on_remove)
+    }
+
+    /// Wraps an existing [`SlotMap`], calling `on_remove` for every element
+    /// removed from now on. Keys of the slot map stay valid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::*;
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut sm = SlotMap::new();
+    /// let k = sm.insert(1);
+    /// let osm = ObservedSlotMap::from_slot_map(sm, |_, _: &i32| {});
+    /// assert_eq!(osm[k], 1);
+    /// ```
+    pub fn from_slot_map(sm: SlotMap<K, V>, on_remove: F) -> Self {
+        Self { sm, on_remove }
+    }
+
+    /// Unwraps the underlying [`SlotMap`], without calling the hook for the
+    /// remaining elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut osm = ObservedSlotMap::new(|_, _: &i32| panic!());
+    /// let k = osm.insert(1);
+    /// let sm = osm.into_inner();
+    /// assert_eq!(sm[k], 1);
+    /// ```
+    pub fn into_inner(self) -> SlotMap<K, V> {
+        self.sm
+    }
+
+    /// Returns a reference to the underlying [`SlotMap`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut osm = ObservedSlotMap::new(|_, _: &i32| {});
+    /// osm.insert(1);
+    /// assert_eq!(osm.as_slot_map().capacity(), osm.capacity());
+    /// ```
+    pub fn as_slot_map(&self) -> &SlotMap<K, V> {
+        &self.sm
+    }
+
+    /// Returns the number of elements in the slot map. See [`SlotMap::len`].
+    pub fn len(&self) -> usize {
+        self.sm.len()
+    }
+
+    /// Returns if the slot map is empty. See [`SlotMap::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.sm.is_empty()
+    }
+
+    /// Returns the number of elements the slot map can hold without
+    /// reallocating. See [`SlotMap::capacity`].
+    pub fn capacity(&self) -> usize {
+        self.sm.capacity()
+    }
+
+    /// Reserves capacity for at least `additional` more elements. See
+    /// [`SlotMap::reserve`].
+    pub fn reserve(&mut self, additional: usize) {
+        self.sm.reserve(additional)
+    }
+
+    /// Returns [`true`] if the slot map contains `key`. See
+    /// [`SlotMap::contains_key`].
+    pub fn contains_key(&self, key: K) -> bool {
+        self.sm.contains_key(key)
+    }
+
+    /// Inserts a value into the slot map, returning its key. See
+    /// [`SlotMap::insert`].
+    pub fn insert(&mut self, value: V) -> K {
+        self.sm.insert(value)
+    }
+
+    /// Inserts a value given by `f` into the slot map. The key where the
+    /// value will be stored is passed into `f`. See
+    /// [`SlotMap::insert_with_key`].
+    pub fn insert_with_key<G>(&mut self, f: G) -> K
+    where
+        G: FnOnce(K) -> V,
+    {
+        self.sm.insert_with_key(f)
+    }
+
+    /// Removes a key from the slot map, calling the hook and returning the
+    /// value at the key if the key was not previously removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut removed = 0;
+    /// let mut sm = ObservedSlotMap::new(|_, _: &i32| removed += 1);
+    /// let key = sm.insert(42);
+    /// assert_eq!(sm.remove(key), Some(42));
+    /// assert_eq!(sm.remove(key), None);
+    /// drop(sm);
+    /// assert_eq!(removed, 1);
+    /// ```
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let value = self.sm.remove(key)?;
+        (self.on_remove)(key, &value);
+        Some(value)
+    }
+
+    /// Removes a key from the slot map if `f` returns true for its value,
+    /// calling the hook and returning the removed value. See
+    /// [`SlotMap::remove_if`].
+    pub fn remove_if<G>(&mut self, key: K, f: G) -> Option<V>
+    where
+        G: FnOnce(&V) -> bool,
+    {
+        let value = self.sm.remove_if(key, f)?;
+        (self.on_remove)(key, &value);
+        Some(value)
+    }
+
+    /// Removes all given keys from the slot map, calling the hook for each
+    /// removed element and returning how many elements were actually removed.
+    /// See [`SlotMap::remove_many`].
+    pub fn remove_many(&mut self, keys: &[K]) -> usize {
+        let mut removed = 0;
+        for &key in keys {
+            if self.remove(key).is_some() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Retains only the elements specified by the predicate, calling the hook
+    /// for every element that is removed. See [`SlotMap::retain`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut removed = Vec::new();
+    /// let mut sm = ObservedSlotMap::new(|_, v: &i32| removed.push(*v));
+    /// for i in 0..5 {
+    ///     sm.insert(i);
+    /// }
+    /// sm.retain(|_, v| *v % 2 == 0);
+    /// assert_eq!(sm.len(), 3);
+    /// drop(sm);
+    /// assert_eq!(removed, vec![1, 3]);
+    /// ```
+    pub fn retain<G>(&mut self, mut f: G)
+    where
+        G: FnMut(K, &mut V) -> bool,
+    {
+        let on_remove = &mut self.on_remove;
+        self.sm.retain(|key, value| {
+            let keep = f(key, value);
+            if !keep {
+                on_remove(key, value);
+            }
+            keep
+        });
+    }
+
+    /// Clears the slot map, calling the hook for every element. Keeps the
+    /// allocated memory for reuse. See [`SlotMap::clear`].
+    pub fn clear(&mut self) {
+        self.drain();
+    }
+
+    /// Clears the slot map, returning all key-value pairs as an iterator.
+    /// Keeps the allocated memory for reuse. See [`SlotMap::drain`].
+    ///
+    /// The hook is called for every element as it is yielded. When the
+    /// iterator is dropped early, the hook is called for all elements that
+    /// were not yielded yet as they are removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use slotmap::observed::ObservedSlotMap;
+    /// let mut removed = 0;
+    /// let mut sm = ObservedSlotMap::new(|_, _: &i32| removed += 1);
+    /// for i in 0..5 {
+    ///     sm.insert(i);
+    /// }
+    /// assert_eq!(sm.drain().next().map(|(_, v)| v), Some(0));
+    /// assert!(sm.is_empty());
+    /// drop(sm);
+    /// assert_eq!(removed, 5);
+    /// ```
+    pub fn drain(&mut self) -> Drain<'_, K, V, F> {
+        Drain {
+            inner: self.sm.drain(),
+            on_remove: &mut self.on_remove,
+        }
+    }
+
+    /// Creates an iterator which uses `f` to decide if an element should be
+    /// removed, calling the hook for every removed element. See
+    /// [`SlotMap::drain_filter`].
+    pub fn drain_filter<G>(&mut self, f: G) -> DrainFilter<'_, K, V, F, G>
+    where
+        G: FnMut(K, &mut V) -> bool,
+    {
+        DrainFilter {
+            inner: self.sm.drain_filter(f),
+            on_remove: &mut self.on_remove,
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key. See
+    /// [`SlotMap::get`].
+    pub fn get(&self, key: K) -> Option<&V> {
+        self.sm.get(key)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key. See
+    /// [`SlotMap::get_mut`].
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.sm.get_mut(key)
+    }
+
+    /// An iterator visiting all key-value pairs. See [`SlotMap::iter`].
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.sm.iter()
+    }
+
+    /// An iterator visiting all key-value pairs, with mutable references to
+    /// the values. See [`SlotMap::iter_mut`].
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        self.sm.iter_mut()
+    }
+
+    /// An iterator visiting all keys. See [`SlotMap::keys`].
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        self.sm.keys()
+    }
+
+    /// An iterator visiting all values. See [`SlotMap::values`].
+    pub fn values(&self) -> Values<'_, K, V> {
+        self.sm.values()
+    }
+
+    /// An iterator visiting all values mutably. See [`SlotMap::values_mut`].
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        self.sm.values_mut()
+    }
+}
+
+impl<K: Key, V: fmt::Debug, F> fmt::Debug for ObservedSlotMap<K, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObservedSlotMap")
+            .field("sm", &self.sm)
+            .finish()
+    }
+}
+
+impl<K: Key, V, F> Index<K> for ObservedSlotMap<K, V, F>
+where
+    F: FnMut(K, &V),
+{
+    type Output = V;
+
+    fn index(&self, key: K) -> &V {
+        &self.sm[key]
+    }
+}
+
+impl<K: Key, V, F> IndexMut<K> for ObservedSlotMap<K, V, F>
+where
+    F: FnMut(K, &V),
+{
+    fn index_mut(&mut self, key: K) -> &mut V {
+        &mut self.sm[key]
+    }
+}
+
+impl<'a, K: Key, V, F> IntoIterator for &'a ObservedSlotMap<K, V, F>
+where
+    F: FnMut(K, &V),
+{
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Key, V, F> IntoIterator for &'a mut ObservedSlotMap<K, V, F>
+where
+    F: FnMut(K, &V),
+{
+    type Item = (K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// A draining iterator for [`ObservedSlotMap`].
+///
+/// This iterator is created by [`ObservedSlotMap::drain`].
+pub struct Drain<'a, K: 'a + Key, V: 'a, F: FnMut(K, &V)> {
+    inner: basic::Drain<'a, K, V>,
+    on_remove: &'a mut F,
+}
+
+/// A draining, filtering iterator for [`ObservedSlotMap`].
+///
+/// This iterator is created by [`ObservedSlotMap::drain_filter`].
+pub struct DrainFilter<'a, K: 'a + Key, V: 'a, F, G> {
+    inner: basic::DrainFilter<'a, K, V, G>,
+    on_remove: &'a mut F,
+}
+
+impl<'a, K: Key, V: fmt::Debug, F: FnMut(K, &V)> fmt::Debug for Drain<'a, K, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Drain").field(&self.inner).finish()
+    }
+}
+
+impl<'a, K: Key, V, F, G> fmt::Debug for DrainFilter<'a, K, V, F, G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DrainFilter").field(&self.inner).finish()
+    }
+}
+
+impl<'a, K: Key, V, F> Iterator for Drain<'a, K, V, F>
+where
+    F: FnMut(K, &V),
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let (key, value) = self.inner.next()?;
+        (self.on_remove)(key, &value);
+        Some((key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K: Key, V, F: FnMut(K, &V)> Drop for Drain<'a, K, V, F> {
+    fn drop(&mut self) {
+        // The inner iterator would remove the remaining elements on its own,
+        // but without reporting them.
+        self.for_each(|_drop| {});
+    }
+}
+
+impl<'a, K: Key, V, F, G> Iterator for DrainFilter<'a, K, V, F, G>
+where
+    F: FnMut(K, &V),
+    G: FnMut(K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let (key, value) = self.inner.next()?;
+        (self.on_remove)(key, &value);
+        Some((key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K: Key, V, F: FnMut(K, &V)> FusedIterator for Drain<'a, K, V, F> {}
+impl<'a, K: Key, V, F, G> FusedIterator for DrainFilter<'a, K, V, F, G>
+where
+    F: FnMut(K, &V),
+    G: FnMut(K, &mut V) -> bool,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    #[test]
+    fn hook_called_once_per_removal() {
+        let removed = RefCell::new(Vec::new());
+        let mut sm = ObservedSlotMap::new(|k, v: &i32| removed.borrow_mut().push((k, *v)));
+        let keys: Vec<_> = (0..10).map(|i| sm.insert(i)).collect();
+
+        assert_eq!(sm.remove(keys[0]), Some(0));
+        assert_eq!(sm.remove(keys[0]), None);
+        assert_eq!(sm.remove_if(keys[1], |_| false), None);
+        assert_eq!(sm.remove_if(keys[1], |_| true), Some(1));
+        assert_eq!(sm.remove_many(&[keys[2], keys[3], keys[2]]), 2);
+        sm.retain(|_, v| *v != 4);
+        let odd: Vec<_> = sm.drain_filter(|_, v| *v % 2 == 1).collect();
+        assert_eq!(odd, vec![(keys[5], 5), (keys[7], 7), (keys[9], 9)]);
+        assert_eq!(removed.borrow().len(), 8);
+
+        sm.clear();
+        assert!(sm.is_empty());
+        let expected = vec![0, 1, 2, 3, 4, 5, 7, 9, 6, 8];
+        let got: Vec<_> = removed.borrow().iter().map(|&(_, v)| v).collect();
+        assert_eq!(got, expected);
+        assert!(removed.borrow().iter().all(|&(k, v)| keys[v as usize] == k));
+    }
+
+    #[test]
+    fn drain_dropped_early() {
+        let count = RefCell::new(0);
+        let mut sm = ObservedSlotMap::new(|_, _: &i32| *count.borrow_mut() += 1);
+        for i in 0..5 {
+            sm.insert(i);
+        }
+
+        let mut drain = sm.drain();
+        drain.next();
+        drain.next();
+        assert_eq!(*count.borrow(), 2);
+        drop(drain);
+        assert_eq!(*count.borrow(), 5);
+        assert!(sm.is_empty());
+
+        // Draining an empty map and dropping the rest doesn't call the hook.
+        sm.drain();
+        sm.clear();
+        assert_eq!(*count.borrow(), 5);
+    }
+
+    #[test]
+    fn drain_filter_dropped_early() {
+        let count = RefCell::new(0);
+        let mut sm = ObservedSlotMap::new(|_, _: &i32| *count.borrow_mut() += 1);
+        for i in 0..5 {
+            sm.insert(i);
+        }
+
+        // Unvisited elements stay in the map and are not reported.
+        sm.drain_filter(|_, _| true).next();
+        assert_eq!(*count.borrow(), 1);
+        assert_eq!(sm.len(), 4);
+    }
+
+    #[test]
+    fn into_inner_does_not_report() {
+        let count = RefCell::new(0);
+        let mut sm = ObservedSlotMap::new(|_, _: &i32| *count.borrow_mut() += 1);
+        let k = sm.insert(1);
+        sm[k] += 1;
+        let inner = sm.into_inner();
+        assert_eq!(inner[k], 2);
+        assert_eq!(*count.borrow(), 0);
+    }
+}